@@ -1,23 +1,53 @@
+pub mod registry;
 pub mod speech_to_text;
-pub use hudagents_local::whisper::HAWhisperError;
+pub mod transcript;
+pub mod vision;
+use crate::{
+    context::{
+        AgentContext, Control,
+        blob::{Blob, MediaKind},
+        ids::RunId,
+        message::MessagePayload,
+    },
+    graph::NodeId,
+};
+pub use hudagents_local::{
+    audio::{PcmAudio, PcmSamples},
+    ollama::HAOllamaError,
+    vad::HAVadError,
+    whisper::HAWhisperError,
+};
 use std::{
     error::Error,
     fmt::{self, Debug, Display},
+    future::Future,
+    pin::Pin,
+    sync::Arc,
 };
+use tokio::runtime::{Builder, Runtime};
+pub use transcript::{Transcript, TranscriptSegment, TranscriptWord};
 
 #[derive(Debug)]
 pub enum HAAgentError {
     InvalidInput(String),
+    InvalidConfig(String),
+    UnknownKind(String),
     Whisper(HAWhisperError),
+    Ollama(HAOllamaError),
+    Vad(HAVadError),
 }
 
 impl Display for HAAgentError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             HAAgentError::InvalidInput(msg) => write!(f, "agent Input Error: {}", msg),
+            HAAgentError::InvalidConfig(msg) => write!(f, "invalid agent config: {}", msg),
+            HAAgentError::UnknownKind(kind) => write!(f, "unknown agent kind: {}", kind),
             HAAgentError::Whisper(msg) => {
                 write!(f, "audio transcription failed: {}", msg)
             }
+            HAAgentError::Ollama(msg) => write!(f, "image interpretation failed: {}", msg),
+            HAAgentError::Vad(msg) => write!(f, "speech detection failed: {}", msg),
         }
     }
 }
@@ -28,34 +58,523 @@ impl From<HAWhisperError> for HAAgentError {
     }
 }
 
+impl From<HAOllamaError> for HAAgentError {
+    fn from(e: HAOllamaError) -> Self {
+        HAAgentError::Ollama(e)
+    }
+}
+
+impl From<HAVadError> for HAAgentError {
+    fn from(e: HAVadError) -> Self {
+        HAAgentError::Vad(e)
+    }
+}
+
 impl Error for HAAgentError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
             HAAgentError::Whisper(e) => Some(e),
+            HAAgentError::Ollama(e) => Some(e),
+            HAAgentError::Vad(e) => Some(e),
             _ => None,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum AgentInput {
-    // TODO: Add more Audio variants AudioM4a, AudioPcm, etc.
-    Audio(Vec<u8>),
-    Image(Vec<u8>),
+    // Any supported container; the format is sniffed from the bytes.
+    Audio(Blob),
+    // Raw samples with a declared format, passed to whisper without a decoding step.
+    AudioPcm(PcmAudio),
+    AudioWav(Blob),
+    // Ogg/Opus frames.
+    AudioOpus(Blob),
+    Image(Blob),
     Text(String),
+    // Outputs of every upstream node of a fan-in node (a node with several predecessors).
+    Combined(CombinedInput),
 }
 
-#[derive(Debug)]
+// Rejects blobs sniffed as the wrong kind of media. Unrecognised bytes are let through, the
+// decoder gets the final say.
+pub fn check_media(blob: &Blob, expected: MediaKind) -> Result<(), HAAgentError> {
+    match (blob.mime, blob.kind()) {
+        (Some(mime), Some(kind)) if kind != expected => Err(HAAgentError::InvalidInput(format!(
+            "expected {} input, got {}",
+            match expected {
+                MediaKind::Audio => "audio",
+                MediaKind::Image => "image",
+            },
+            mime
+        ))),
+        _ => Ok(()),
+    }
+}
+
+// Rejects blobs sniffed as a format other than the declared one.
+pub fn check_format(blob: &Blob, expected: &[&str]) -> Result<(), HAAgentError> {
+    match blob.mime {
+        Some(mime) if !expected.contains(&mime) => Err(HAAgentError::InvalidInput(format!(
+            "expected {} input, got {}",
+            expected.join(" or "),
+            mime
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UpstreamOutput {
+    pub node: NodeId,
+    pub name: String,
+    pub output: AgentOutput,
+}
+
+// Upstream outputs in the order the edges were added. Predecessors whose edge did not fire
+// (skipped or filtered by a condition) are absent.
+#[derive(Clone, Debug, Default)]
+pub struct CombinedInput {
+    pub inputs: Vec<UpstreamOutput>,
+}
+
+impl CombinedInput {
+    pub fn get(&self, name: &str) -> Option<&AgentOutput> {
+        self.iter()
+            .find(|upstream| upstream.name == name)
+            .map(|upstream| &upstream.output)
+    }
+
+    pub fn by_node(&self, node: NodeId) -> Option<&AgentOutput> {
+        self.iter()
+            .find(|upstream| upstream.node == node)
+            .map(|upstream| &upstream.output)
+    }
+
+    pub fn transcription(&self) -> Option<&str> {
+        self.iter().find_map(|upstream| match &upstream.output {
+            AgentOutput::AudioTranscription(transcript) => Some(transcript.text.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn image_interpretation(&self) -> Option<&str> {
+        self.iter().find_map(|upstream| match &upstream.output {
+            AgentOutput::ImageInterpretation(text) => Some(text.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &UpstreamOutput> {
+        self.inputs.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    // Every upstream text, one per line, for agents that only understand text.
+    pub fn to_text(&self) -> String {
+        self.iter()
+            .map(|upstream| upstream.output.text())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[derive(Clone, Debug)]
 pub enum AgentOutput {
-    AudioTranscription(String),
+    AudioTranscription(Transcript),
     ImageInterpretation(String),
     FinalAnswer(String),
 }
 
+impl AgentOutput {
+    pub fn text(&self) -> &str {
+        match self {
+            AgentOutput::AudioTranscription(transcript) => &transcript.text,
+            AgentOutput::ImageInterpretation(text) | AgentOutput::FinalAnswer(text) => text,
+        }
+    }
+}
+
+// Every output is textual today, so downstream nodes receive it as text input.
+impl From<AgentOutput> for AgentInput {
+    fn from(output: AgentOutput) -> Self {
+        match output {
+            AgentOutput::AudioTranscription(Transcript { text, .. })
+            | AgentOutput::ImageInterpretation(text)
+            | AgentOutput::FinalAnswer(text) => AgentInput::Text(text),
+        }
+    }
+}
+
+// What an agent sees of the conversation during a context-aware call: a read-only snapshot of
+// the history taken when its layer started, plus the messages it emits. The runtime appends the
+// emitted messages after the node's output, and an emitted `Control` is handled as if the
+// runner's controller had returned it.
+pub struct CallContext {
+    node: NodeId,
+    history: Arc<AgentContext>,
+    emitted: Vec<MessagePayload>,
+}
+
+impl CallContext {
+    pub fn new(node: NodeId, history: Arc<AgentContext>) -> Self {
+        Self {
+            node,
+            history,
+            emitted: Vec::new(),
+        }
+    }
+
+    pub fn node(&self) -> NodeId {
+        self.node
+    }
+
+    pub fn run_id(&self) -> &RunId {
+        &self.history.run_id
+    }
+
+    pub fn history(&self) -> &AgentContext {
+        &self.history
+    }
+
+    pub fn emit(&mut self, payload: MessagePayload) {
+        self.emitted.push(payload);
+    }
+
+    pub fn emit_control(&mut self, control: Control) {
+        self.emit(MessagePayload::Control(control));
+    }
+
+    pub fn emit_error(&mut self, msg: impl Into<String>) {
+        self.emit(MessagePayload::Error(msg.into()));
+    }
+
+    pub fn emitted(&self) -> &[MessagePayload] {
+        &self.emitted
+    }
+
+    pub fn take_emitted(&mut self) -> Vec<MessagePayload> {
+        std::mem::take(&mut self.emitted)
+    }
+
+    // The last `Control` the agent emitted.
+    pub fn control(&self) -> Option<&Control> {
+        self.emitted.iter().rev().find_map(|payload| match payload {
+            MessagePayload::Control(control) => Some(control),
+            _ => None,
+        })
+    }
+
+    // Same node and history, nothing emitted yet.
+    pub fn fork(&self) -> Self {
+        Self::new(self.node, Arc::clone(&self.history))
+    }
+}
+
 pub trait Agent {
     fn id(&self) -> &str;
     fn call(&self, agent_input: AgentInput) -> Result<AgentOutput, HAAgentError>;
+    // Called by the runtime. Stateful agents override it to read the history or emit messages;
+    // the default ignores the context.
+    fn call_with_context(
+        &self,
+        agent_input: AgentInput,
+        cx: &mut CallContext,
+    ) -> Result<AgentOutput, HAAgentError> {
+        let _ = cx;
+        self.call(agent_input)
+    }
+    fn describe(&self) -> String {
+        self.id().to_string()
+    }
+}
+
+pub type AgentFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<AgentOutput, HAAgentError>> + Send + 'a>>;
+
+// Non-blocking counterpart of `Agent` for agents that wait on I/O (HTTP model servers, cloud LLMs).
+pub trait AsyncAgent {
+    fn id(&self) -> &str;
+    fn call(&self, agent_input: AgentInput) -> AgentFuture<'_>;
+    // See `Agent::call_with_context`.
+    fn call_with_context<'a>(
+        &'a self,
+        agent_input: AgentInput,
+        cx: &'a mut CallContext,
+    ) -> AgentFuture<'a> {
+        let _ = cx;
+        self.call(agent_input)
+    }
     fn describe(&self) -> String {
         self.id().to_string()
     }
 }
+
+// Runs a blocking `Agent` as an `AsyncAgent` on tokio's blocking thread pool, so CPU-bound work
+// such as whisper does not stall the async worker threads.
+pub struct SpawnBlocking<A: ?Sized>(pub Arc<A>);
+
+impl<A: Agent + Send + Sync + ?Sized + 'static> SpawnBlocking<A> {
+    pub fn new(agent: Arc<A>) -> Self {
+        Self(agent)
+    }
+}
+
+impl<A: Agent + Send + Sync + ?Sized + 'static> AsyncAgent for SpawnBlocking<A> {
+    fn id(&self) -> &str {
+        self.0.id()
+    }
+
+    fn call(&self, agent_input: AgentInput) -> AgentFuture<'_> {
+        let agent = Arc::clone(&self.0);
+        Box::pin(async move {
+            match tokio::task::spawn_blocking(move || agent.call(agent_input)).await {
+                Ok(result) => result,
+                Err(e) => std::panic::resume_unwind(e.into_panic()),
+            }
+        })
+    }
+
+    // The blocking pool needs an owned context; emitted messages are copied back afterwards.
+    fn call_with_context<'a>(
+        &'a self,
+        agent_input: AgentInput,
+        cx: &'a mut CallContext,
+    ) -> AgentFuture<'a> {
+        let agent = Arc::clone(&self.0);
+        let mut owned = cx.fork();
+        Box::pin(async move {
+            let joined = tokio::task::spawn_blocking(move || {
+                let result = agent.call_with_context(agent_input, &mut owned);
+                (result, owned)
+            })
+            .await;
+            match joined {
+                Ok((result, mut owned)) => {
+                    cx.emitted.append(&mut owned.emitted);
+                    result
+                }
+                Err(e) => std::panic::resume_unwind(e.into_panic()),
+            }
+        })
+    }
+
+    fn describe(&self) -> String {
+        self.0.describe()
+    }
+}
+
+// Drives an `AsyncAgent` from synchronous code on a private current-thread runtime.
+// `call` must not be used from inside another tokio runtime, as tokio panics when asked to
+// block there. Dropping the adapter is fine anywhere, including inside a runtime: the private
+// runtime is shut down without waiting for its tasks.
+pub struct BlockOn<A: ?Sized> {
+    agent: Arc<A>,
+    // Only `None` while dropping.
+    rt: Option<Runtime>,
+}
+
+impl<A: AsyncAgent + ?Sized> BlockOn<A> {
+    pub fn new(agent: Arc<A>) -> std::io::Result<Self> {
+        let rt = Builder::new_current_thread().enable_all().build()?;
+        Ok(Self {
+            agent,
+            rt: Some(rt),
+        })
+    }
+
+    fn rt(&self) -> &Runtime {
+        self.rt.as_ref().expect("runtime is only taken on drop")
+    }
+}
+
+impl<A: ?Sized> Drop for BlockOn<A> {
+    // A plain drop of a `Runtime` panics inside an async context.
+    fn drop(&mut self) {
+        if let Some(rt) = self.rt.take() {
+            rt.shutdown_background();
+        }
+    }
+}
+
+impl<A: AsyncAgent + ?Sized> Agent for BlockOn<A> {
+    fn id(&self) -> &str {
+        self.agent.id()
+    }
+
+    fn call(&self, agent_input: AgentInput) -> Result<AgentOutput, HAAgentError> {
+        self.rt().block_on(self.agent.call(agent_input))
+    }
+
+    fn call_with_context(
+        &self,
+        agent_input: AgentInput,
+        cx: &mut CallContext,
+    ) -> Result<AgentOutput, HAAgentError> {
+        self.rt()
+            .block_on(self.agent.call_with_context(agent_input, cx))
+    }
+
+    fn describe(&self) -> String {
+        self.agent.describe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct UpperAgent;
+
+    impl Agent for UpperAgent {
+        fn id(&self) -> &str {
+            "upper"
+        }
+
+        fn call(&self, agent_input: AgentInput) -> Result<AgentOutput, HAAgentError> {
+            match agent_input {
+                AgentInput::Text(text) => Ok(AgentOutput::FinalAnswer(text.to_uppercase())),
+                _ => Err(HAAgentError::InvalidInput("expected text input".into())),
+            }
+        }
+    }
+
+    struct ReverseAgent;
+
+    impl AsyncAgent for ReverseAgent {
+        fn id(&self) -> &str {
+            "reverse"
+        }
+
+        fn call(&self, agent_input: AgentInput) -> AgentFuture<'_> {
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+                match agent_input {
+                    AgentInput::Text(text) => {
+                        Ok(AgentOutput::FinalAnswer(text.chars().rev().collect()))
+                    }
+                    _ => Err(HAAgentError::InvalidInput("expected text input".into())),
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn combined_input_lookups() {
+        let combined = CombinedInput {
+            inputs: vec![
+                UpstreamOutput {
+                    node: NodeId(1),
+                    name: "listen".into(),
+                    output: AgentOutput::AudioTranscription("what is this".into()),
+                },
+                UpstreamOutput {
+                    node: NodeId(2),
+                    name: "vision".into(),
+                    output: AgentOutput::ImageInterpretation("a mug".into()),
+                },
+            ],
+        };
+
+        assert_eq!(combined.len(), 2);
+        assert_eq!(combined.transcription(), Some("what is this"));
+        assert_eq!(combined.image_interpretation(), Some("a mug"));
+        assert_eq!(combined.get("vision").map(AgentOutput::text), Some("a mug"));
+        assert_eq!(
+            combined.by_node(NodeId(1)).map(AgentOutput::text),
+            Some("what is this")
+        );
+        assert!(combined.get("missing").is_none());
+        assert_eq!(combined.to_text(), "what is this\na mug");
+    }
+
+    #[test]
+    fn spawn_blocking_runs_agent_from_async_code() {
+        let rt = Builder::new_current_thread().build().unwrap();
+        let agent = SpawnBlocking::new(Arc::new(UpperAgent));
+
+        let output = rt
+            .block_on(AsyncAgent::call(&agent, AgentInput::Text("hud".into())))
+            .unwrap();
+        assert_eq!(output.text(), "HUD");
+        assert_eq!(AsyncAgent::id(&agent), "upper");
+    }
+
+    struct EmitAgent;
+
+    impl Agent for EmitAgent {
+        fn id(&self) -> &str {
+            "emit"
+        }
+
+        fn call(&self, _agent_input: AgentInput) -> Result<AgentOutput, HAAgentError> {
+            Ok(AgentOutput::FinalAnswer(String::new()))
+        }
+
+        fn call_with_context(
+            &self,
+            agent_input: AgentInput,
+            cx: &mut CallContext,
+        ) -> Result<AgentOutput, HAAgentError> {
+            cx.emit_error("heads up");
+            cx.emit_control(Control::SkipNode(cx.node()));
+            self.call(agent_input)
+        }
+    }
+
+    #[test]
+    fn spawn_blocking_forwards_emitted_messages() {
+        use crate::context::ids::UserId;
+
+        let rt = Builder::new_current_thread().build().unwrap();
+        let agent = SpawnBlocking::new(Arc::new(EmitAgent));
+        let history = Arc::new(AgentContext::new(RunId(1), UserId(1), 4));
+        let mut cx = CallContext::new(NodeId(3), history);
+
+        rt.block_on(agent.call_with_context(AgentInput::Text(String::new()), &mut cx))
+            .unwrap();
+        assert_eq!(cx.emitted().len(), 2);
+        assert!(matches!(cx.control(), Some(Control::SkipNode(NodeId(3)))));
+    }
+
+    #[test]
+    fn block_on_runs_async_agent_from_sync_code() {
+        let agent = BlockOn::new(Arc::new(ReverseAgent)).unwrap();
+
+        let output = Agent::call(&agent, AgentInput::Text("hud".into())).unwrap();
+        assert_eq!(output.text(), "duh");
+
+        let err = Agent::call(&agent, AgentInput::Audio(Blob::new(vec![]))).unwrap_err();
+        assert!(matches!(err, HAAgentError::InvalidInput(_)));
+    }
+
+    #[test]
+    fn block_on_can_be_dropped_inside_a_runtime() {
+        let agent = BlockOn::new(Arc::new(ReverseAgent)).unwrap();
+        let rt = Builder::new_current_thread().build().unwrap();
+
+        rt.block_on(async move { drop(agent) });
+    }
+
+    #[test]
+    fn check_format_rejects_other_declared_format() {
+        use crate::context::blob::media::{AUDIO_OGG, AUDIO_OPUS, AUDIO_WAV};
+
+        let wav = Blob::new(b"RIFF\0\0\0\0WAVEfmt ".to_vec());
+        assert!(check_format(&wav, &[AUDIO_WAV]).is_ok());
+        let err = check_format(&wav, &[AUDIO_OPUS, AUDIO_OGG]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "agent Input Error: expected audio/opus or audio/ogg input, got audio/wav"
+        );
+        // Unrecognised bytes are left to the decoder.
+        assert!(check_format(&Blob::new(vec![1, 2, 3]), &[AUDIO_WAV]).is_ok());
+    }
+}
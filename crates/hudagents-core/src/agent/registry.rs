@@ -0,0 +1,339 @@
+// Agent registry: builds agents by kind name from string parameters.
+use super::{
+    Agent, HAAgentError,
+    speech_to_text::{SpeechToTextAgent, TranscribeOptions},
+    vision::{DEFAULT_OLLAMA_URL, DEFAULT_VISION_PROMPT, HALocalOllama, VisionAgent},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+pub type AgentConfig = BTreeMap<String, String>;
+
+// Params: `model_path`, optional `language` (`auto` to detect it), `languages` (comma-separated
+// list to detect from, only with `auto` or no `language`), `translate`, `beam_size`,
+// `temperature`, `initial_prompt` and `threads`.
+pub const SPEECH_TO_TEXT: &str = "speech_to_text";
+// Params: `model`, optional `prompt`, `base_url` (Ollama server) and `timeout_secs`.
+pub const VISION: &str = "vision";
+
+const SPEECH_TO_TEXT_PARAMS: &[&str] = &[
+    "model_path",
+    "language",
+    "languages",
+    "translate",
+    "beam_size",
+    "temperature",
+    "initial_prompt",
+    "threads",
+];
+const VISION_PARAMS: &[&str] = &["model", "prompt", "base_url", "timeout_secs"];
+
+// Receives the agent id and its parameters.
+pub type AgentConstructor = Arc<
+    dyn Fn(&str, &AgentConfig) -> Result<Arc<dyn Agent + Send + Sync>, HAAgentError> + Send + Sync,
+>;
+
+#[derive(Clone, Default)]
+pub struct AgentRegistry {
+    kinds: HashMap<String, AgentConstructor>,
+}
+
+impl AgentRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Registry preloaded with the agents shipped in this crate. Downstream crates add their own
+    // kinds with `register`.
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register(SPEECH_TO_TEXT, |id, config| {
+            known(config, SPEECH_TO_TEXT_PARAMS)?;
+            let model_path = required(config, "model_path")?;
+            let options = transcribe_options(config)?;
+            Ok(Arc::new(
+                SpeechToTextAgent::new(id.to_string(), model_path.to_string())?
+                    .with_options(options),
+            ))
+        });
+        registry.register(VISION, |id, config| {
+            known(config, VISION_PARAMS)?;
+            let mut ollama = HALocalOllama::new(optional(config, "base_url", DEFAULT_OLLAMA_URL));
+            if let Some(secs) = parsed(config, "timeout_secs")? {
+                ollama = ollama.with_timeout(Duration::from_secs(secs));
+            }
+            Ok(Arc::new(VisionAgent::new(
+                id.to_string(),
+                ollama,
+                required(config, "model")?,
+                optional(config, "prompt", DEFAULT_VISION_PROMPT),
+            )))
+        });
+        registry
+    }
+
+    // Registers `constructor` under `kind`, replacing any previous constructor for that kind.
+    pub fn register(
+        &mut self,
+        kind: impl Into<String>,
+        constructor: impl Fn(&str, &AgentConfig) -> Result<Arc<dyn Agent + Send + Sync>, HAAgentError>
+        + Send
+        + Sync
+        + 'static,
+    ) {
+        self.kinds.insert(kind.into(), Arc::new(constructor));
+    }
+
+    pub fn contains(&self, kind: &str) -> bool {
+        self.kinds.contains_key(kind)
+    }
+
+    pub fn kinds(&self) -> impl Iterator<Item = &str> {
+        self.kinds.keys().map(String::as_str)
+    }
+
+    pub fn create(
+        &self,
+        kind: &str,
+        id: &str,
+        config: &AgentConfig,
+    ) -> Result<Arc<dyn Agent + Send + Sync>, HAAgentError> {
+        let constructor = self
+            .kinds
+            .get(kind)
+            .ok_or_else(|| HAAgentError::UnknownKind(kind.to_string()))?;
+        constructor(id, config)
+    }
+}
+
+// Returns the parameter `key` or an `InvalidConfig` error naming the missing key.
+pub fn required<'a>(config: &'a AgentConfig, key: &str) -> Result<&'a str, HAAgentError> {
+    config
+        .get(key)
+        .map(String::as_str)
+        .ok_or_else(|| HAAgentError::InvalidConfig(format!("missing parameter `{key}`")))
+}
+
+// Rejects parameters outside `keys`, so a misspelt one is not silently ignored.
+pub fn known(config: &AgentConfig, keys: &[&str]) -> Result<(), HAAgentError> {
+    match config.keys().find(|key| !keys.contains(&key.as_str())) {
+        Some(key) => Err(HAAgentError::InvalidConfig(format!(
+            "unknown parameter `{key}`"
+        ))),
+        None => Ok(()),
+    }
+}
+
+pub fn optional<'a>(config: &'a AgentConfig, key: &str, default: &'a str) -> &'a str {
+    config.get(key).map(String::as_str).unwrap_or(default)
+}
+
+// Parses the parameter `key` if present.
+pub fn parsed<T: FromStr>(config: &AgentConfig, key: &str) -> Result<Option<T>, HAAgentError> {
+    config
+        .get(key)
+        .map(|value| {
+            value.parse().map_err(|_| {
+                HAAgentError::InvalidConfig(format!("invalid value `{value}` for `{key}`"))
+            })
+        })
+        .transpose()
+}
+
+fn transcribe_options(config: &AgentConfig) -> Result<TranscribeOptions, HAAgentError> {
+    let mut options = TranscribeOptions::new();
+    match config.get("language").map(String::as_str) {
+        Some("auto") => options = options.with_auto_language(),
+        Some(_) if config.contains_key("languages") => {
+            return Err(HAAgentError::InvalidConfig(
+                "`languages` needs `language` to be `auto` or unset".to_string(),
+            ));
+        }
+        Some(language) => options = options.with_language(language),
+        None => {}
+    }
+    if let Some(languages) = config.get("languages") {
+        let languages = languages
+            .split(',')
+            .map(str::trim)
+            .filter(|l| !l.is_empty());
+        options = options.with_allowed_languages(languages);
+    }
+    if let Some(translate) = parsed(config, "translate")? {
+        options = options.with_translate(translate);
+    }
+    if let Some(beam_size) = parsed(config, "beam_size")? {
+        // whisper.cpp does not implement patience; -1.0 is its default.
+        options = options.with_beam_search(beam_size, -1.0);
+    }
+    if let Some(temperature) = parsed(config, "temperature")? {
+        options.temperature = temperature;
+    }
+    if let Some(prompt) = config.get("initial_prompt") {
+        options = options.with_initial_prompt(prompt.as_str());
+    }
+    if let Some(threads) = parsed(config, "threads")? {
+        options = options.with_threads(threads);
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{AgentInput, AgentOutput};
+
+    struct ConstAgent(String);
+
+    impl Agent for ConstAgent {
+        fn id(&self) -> &str {
+            "const"
+        }
+
+        fn call(&self, _agent_input: AgentInput) -> Result<AgentOutput, HAAgentError> {
+            Ok(AgentOutput::FinalAnswer(self.0.clone()))
+        }
+    }
+
+    fn registry() -> AgentRegistry {
+        let mut registry = AgentRegistry::new();
+        registry.register("const", |_, config| {
+            Ok(Arc::new(ConstAgent(
+                required(config, "answer")?.to_string(),
+            )))
+        });
+        registry
+    }
+
+    #[test]
+    fn create_builds_registered_kind() {
+        let config = AgentConfig::from([("answer".to_string(), "42".to_string())]);
+
+        let agent = registry().create("const", "a", &config).unwrap();
+        let output = agent.call(AgentInput::Text(String::new())).unwrap();
+        assert_eq!(output.text(), "42");
+    }
+
+    #[test]
+    fn with_builtins_registers_shipped_kinds() {
+        let registry = AgentRegistry::with_builtins();
+        assert!(registry.contains(SPEECH_TO_TEXT));
+        assert!(registry.contains(VISION));
+    }
+
+    #[test]
+    fn builtin_vision_uses_config() {
+        let config = AgentConfig::from([("model".to_string(), "qwen3-vl".to_string())]);
+
+        let agent = AgentRegistry::with_builtins()
+            .create(VISION, "eyes", &config)
+            .unwrap();
+        assert_eq!(agent.id(), "eyes");
+        assert_eq!(agent.describe(), "VisionAgent(eyes, model=qwen3-vl)");
+
+        let err = agent.call(AgentInput::Text(String::new())).unwrap_err();
+        assert!(matches!(err, HAAgentError::InvalidInput(_)));
+
+        let config = AgentConfig::from([
+            ("model".to_string(), "qwen3-vl".to_string()),
+            ("timeout_secs".to_string(), "soon".to_string()),
+        ]);
+        let err = AgentRegistry::with_builtins()
+            .create(VISION, "eyes", &config)
+            .err()
+            .expect("expected invalid timeout");
+        assert!(matches!(err, HAAgentError::InvalidConfig(msg) if msg.contains("timeout_secs")));
+    }
+
+    #[test]
+    fn builtin_speech_to_text_requires_model_path() {
+        let err = AgentRegistry::with_builtins()
+            .create(SPEECH_TO_TEXT, "ears", &AgentConfig::new())
+            .err()
+            .expect("expected missing parameter");
+        assert!(matches!(err, HAAgentError::InvalidConfig(msg) if msg.contains("model_path")));
+    }
+
+    #[test]
+    fn speech_to_text_params_configure_decoding() {
+        let config = AgentConfig::from([
+            ("languages".to_string(), "ro, es".to_string()),
+            ("beam_size".to_string(), "5".to_string()),
+            ("threads".to_string(), "2".to_string()),
+        ]);
+        let options = transcribe_options(&config).unwrap();
+        assert_eq!(options.language, None);
+        assert_eq!(options.allowed_languages, ["ro", "es"]);
+        assert_eq!(options.threads(), 2);
+        assert!(matches!(
+            options.decoding,
+            crate::agent::speech_to_text::Decoding::BeamSearch { beam_size: 5, .. }
+        ));
+
+        let config = AgentConfig::from([("translate".to_string(), "yes".to_string())]);
+        let err = transcribe_options(&config).unwrap_err();
+        assert!(matches!(err, HAAgentError::InvalidConfig(msg) if msg.contains("translate")));
+    }
+
+    #[test]
+    fn speech_to_text_rejects_fixed_language_with_languages() {
+        let config = AgentConfig::from([
+            ("language".to_string(), "ro".to_string()),
+            ("languages".to_string(), "ro, es".to_string()),
+        ]);
+        let err = transcribe_options(&config).unwrap_err();
+        assert!(matches!(err, HAAgentError::InvalidConfig(msg) if msg.contains("languages")));
+
+        let config = AgentConfig::from([
+            ("language".to_string(), "auto".to_string()),
+            ("languages".to_string(), "ro, es".to_string()),
+        ]);
+        assert!(transcribe_options(&config).is_ok());
+    }
+
+    #[test]
+    fn builtins_reject_unknown_params() {
+        for (kind, required) in [(SPEECH_TO_TEXT, "model_path"), (VISION, "model")] {
+            let config = AgentConfig::from([
+                (required.to_string(), "x".to_string()),
+                ("langauge".to_string(), "en".to_string()),
+            ]);
+            let err = AgentRegistry::with_builtins()
+                .create(kind, "a", &config)
+                .err()
+                .expect("expected unknown parameter");
+            assert!(matches!(err, HAAgentError::InvalidConfig(msg) if msg.contains("langauge")));
+        }
+    }
+
+    #[test]
+    fn register_adds_downstream_kind() {
+        let mut registry = AgentRegistry::with_builtins();
+        registry.register("echo", |_, _| Ok(Arc::new(ConstAgent("echo".into()))));
+
+        assert!(registry.contains("echo"));
+        assert!(registry.contains(SPEECH_TO_TEXT));
+    }
+
+    #[test]
+    fn create_rejects_unknown_kind() {
+        let err = registry()
+            .create("missing", "a", &AgentConfig::new())
+            .err()
+            .expect("expected unknown kind");
+        assert!(matches!(err, HAAgentError::UnknownKind(kind) if kind == "missing"));
+    }
+
+    #[test]
+    fn create_reports_missing_parameter() {
+        let err = registry()
+            .create("const", "a", &AgentConfig::new())
+            .err()
+            .expect("expected missing parameter");
+        assert!(matches!(err, HAAgentError::InvalidConfig(msg) if msg.contains("answer")));
+    }
+}
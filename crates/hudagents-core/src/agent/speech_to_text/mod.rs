@@ -1,12 +1,33 @@
-pub use super::{Agent, AgentInput, AgentOutput, HAAgentError};
-pub use hudagents_local::whisper::{HALocalWhisper, HAWhisperError};
+pub mod options;
+pub mod stream;
+
+use super::transcript::group_words;
+pub use super::{
+    Agent, AgentInput, AgentOutput, CallContext, HAAgentError, PcmAudio, Transcript,
+    TranscriptSegment, TranscriptWord, check_format, check_media,
+};
+use crate::context::blob::{
+    MediaKind,
+    media::{AUDIO_OGG, AUDIO_OPUS, AUDIO_WAV},
+};
+use crate::context::ids::UserId;
+use hudagents_local::audio::HAAudioError;
+use hudagents_local::vad::{SpeechSpan, speech_only};
+pub use hudagents_local::{
+    vad::{EnergyVad, VadConfig, VoiceDetector},
+    whisper::{HALocalWhisper, HAWhisperError},
+};
+pub use options::{Decoding, TranscribeOptions};
 use std::{
     borrow::Cow,
+    collections::HashMap,
     io::{Read, Write},
     process::{Command, Stdio},
-    sync::OnceLock,
+    sync::{Arc, OnceLock},
+    time::Duration,
 };
-use whisper_rs::{FullParams, SamplingStrategy, WhisperContext};
+use stream::{StreamConfig, StreamingTranscriber};
+use whisper_rs::{WhisperContext, WhisperState};
 
 pub type WhisperResult<T> = std::result::Result<T, HAWhisperError>;
 
@@ -15,6 +36,9 @@ static FFMPEG_CHECK: OnceLock<Result<(), String>> = OnceLock::new();
 pub struct SpeechToTextAgent {
     id: Cow<'static, str>,
     whisper_context: WhisperContext,
+    vad: Option<Arc<dyn VoiceDetector>>,
+    options: TranscribeOptions,
+    user_languages: HashMap<UserId, Vec<String>>,
 }
 
 impl SpeechToTextAgent {
@@ -22,11 +46,54 @@ impl SpeechToTextAgent {
         id: impl Into<Cow<'static, str>>,
         model_path: impl Into<String>,
     ) -> Result<Self, HAAgentError> {
+        // With native decoding ffmpeg is only a fallback, checked when it is actually needed.
+        #[cfg(not(feature = "native-audio"))]
         ensure_ffmpeg_installed_once()?;
         let whisper_context = HALocalWhisper::new(model_path.into())?.whisper_ctx;
         Ok(Self {
             id: id.into(),
             whisper_context,
+            vad: None,
+            options: TranscribeOptions::new(),
+            user_languages: HashMap::new(),
+        })
+    }
+
+    // Only the speech found by `vad` is transcribed; input without any is answered with an
+    // empty transcription and never reaches whisper.
+    pub fn with_vad(mut self, vad: Arc<dyn VoiceDetector>) -> Self {
+        self.vad = Some(vad);
+        self
+    }
+
+    pub fn with_options(mut self, options: TranscribeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn options(&self) -> &TranscribeOptions {
+        &self.options
+    }
+
+    // Audio from `user` is transcribed in whichever of `languages` whisper detects, regardless
+    // of the language in the options. Applies to context-aware calls only: a plain `call` does
+    // not know the user and keeps to the options. An English-only (`.en`) model fails calls for
+    // users whose languages leave out English.
+    pub fn with_user_languages<S: Into<String>>(
+        mut self,
+        user: UserId,
+        languages: impl IntoIterator<Item = S>,
+    ) -> Self {
+        let languages = languages.into_iter().map(Into::into).collect();
+        self.user_languages.insert(user, languages);
+        self
+    }
+
+    // Live transcription of 16 kHz mono PCM pushed in chunks.
+    pub fn streaming(&self, config: StreamConfig) -> StreamingTranscriber<'_> {
+        StreamingTranscriber::new(config, |pcm| {
+            transcribe_pcm(pcm, &self.whisper_context, &self.options)
+                .map(|transcript| transcript.text)
         })
     }
 }
@@ -36,17 +103,137 @@ impl Agent for SpeechToTextAgent {
         self.id.as_ref()
     }
 
-    // TODO: Use Tokio type calls to make this non blocking
-    // Also for the transcribe method
+    // Blocking: wrap the agent in `SpawnBlocking` to call it from async code. Per-user
+    // languages need the user, so they only apply through `call_with_context`.
     fn call(&self, agent_input: AgentInput) -> Result<AgentOutput, HAAgentError> {
-        match agent_input {
-            AgentInput::Audio(bytes) => {
-                let text = transcribe(&bytes, &self.whisper_context)?;
-                Ok(AgentOutput::AudioTranscription(text))
+        self.transcribe_input(agent_input, &self.options)
+    }
+
+    fn call_with_context(
+        &self,
+        agent_input: AgentInput,
+        cx: &mut CallContext,
+    ) -> Result<AgentOutput, HAAgentError> {
+        let options = user_options(&self.options, &self.user_languages, &cx.history().user_id);
+        self.transcribe_input(agent_input, &options)
+    }
+}
+
+impl SpeechToTextAgent {
+    fn transcribe_input(
+        &self,
+        agent_input: AgentInput,
+        options: &TranscribeOptions,
+    ) -> Result<AgentOutput, HAAgentError> {
+        check_languages(self.whisper_context.is_multilingual(), options)?;
+        let pcm = match &agent_input {
+            AgentInput::Audio(blob) => {
+                check_media(blob, MediaKind::Audio)?;
+                Cow::Owned(decode_to_pcm(&blob.bytes)?)
+            }
+            AgentInput::AudioPcm(pcm) => {
+                check_pcm(pcm)?;
+                pcm.to_whisper_pcm()
             }
-            _ => Err(HAAgentError::InvalidInput("expected audio input".into())),
+            AgentInput::AudioWav(blob) => {
+                check_format(blob, &[AUDIO_WAV])?;
+                Cow::Owned(wav_to_pcm(&blob.bytes)?)
+            }
+            AgentInput::AudioOpus(blob) => {
+                check_format(blob, &[AUDIO_OPUS, AUDIO_OGG])?;
+                Cow::Owned(decode_to_pcm(&blob.bytes)?)
+            }
+            _ => return Err(HAAgentError::InvalidInput("expected audio input".into())),
+        };
+        let transcript = match speech_pcm(self.vad.as_deref(), pcm)? {
+            Some((speech, spans)) => {
+                let mut transcript = transcribe_pcm(&speech, &self.whisper_context, options)?;
+                if !spans.is_empty() {
+                    transcript.map_times(|t| source_time(&spans, t));
+                }
+                transcript
+            }
+            None => Transcript::default(),
+        };
+        Ok(AgentOutput::AudioTranscription(transcript))
+    }
+}
+
+// The options with the user's allowed languages, if they have any.
+fn user_options<'a>(
+    options: &'a TranscribeOptions,
+    user_languages: &HashMap<UserId, Vec<String>>,
+    user: &UserId,
+) -> Cow<'a, TranscribeOptions> {
+    match user_languages.get(user) {
+        Some(languages) => Cow::Owned(
+            options
+                .clone()
+                .with_allowed_languages(languages.iter().cloned()),
+        ),
+        None => Cow::Borrowed(options),
+    }
+}
+
+// An English-only model cannot honour an allow-list without English.
+fn check_languages(multilingual: bool, options: &TranscribeOptions) -> Result<(), HAAgentError> {
+    if multilingual || allows_english(options) {
+        return Ok(());
+    }
+    Err(HAAgentError::InvalidConfig(format!(
+        "the model only knows English, but the allowed languages are {:?}",
+        options.allowed_languages
+    )))
+}
+
+fn allows_english(options: &TranscribeOptions) -> bool {
+    options.allowed_languages.is_empty()
+        || options
+            .allowed_languages
+            .iter()
+            .any(|language| language.eq_ignore_ascii_case("en"))
+}
+
+// The speech to transcribe and the spans it was cut from (empty without a detector).
+type Speech<'a> = (Cow<'a, [f32]>, Vec<SpeechSpan>);
+
+// `None` when the detector finds no speech at all.
+fn speech_pcm<'a>(
+    vad: Option<&dyn VoiceDetector>,
+    pcm: Cow<'a, [f32]>,
+) -> Result<Option<Speech<'a>>, HAAgentError> {
+    let Some(vad) = vad else {
+        return Ok(Some((pcm, Vec::new())));
+    };
+    let spans = vad.speech_spans(&pcm)?;
+    if spans.is_empty() {
+        return Ok(None);
+    }
+    let speech = speech_only(&pcm, &spans);
+    Ok(Some((Cow::Owned(speech), spans)))
+}
+
+// Maps a time in the joined speech spans back onto the audio they were cut from.
+fn source_time(spans: &[SpeechSpan], t: Duration) -> Duration {
+    let mut joined = Duration::ZERO;
+    for (i, span) in spans.iter().enumerate() {
+        let len = span.end_time().saturating_sub(span.start_time());
+        if t < joined + len || i + 1 == spans.len() {
+            return span.start_time() + t.saturating_sub(joined);
         }
+        joined += len;
     }
+    t
+}
+
+fn check_pcm(pcm: &PcmAudio) -> Result<(), HAAgentError> {
+    if pcm.sample_rate == 0 || pcm.channels == 0 {
+        return Err(HAAgentError::InvalidInput(format!(
+            "PCM input needs a sample rate and channel count, got {} Hz, {} channels",
+            pcm.sample_rate, pcm.channels
+        )));
+    }
+    Ok(())
 }
 
 fn ensure_ffmpeg_installed() -> Result<(), HAWhisperError> {
@@ -71,6 +258,32 @@ fn ensure_ffmpeg_installed_once() -> Result<(), HAWhisperError> {
     }
 }
 
+// Decodes to 16 kHz mono. PCM WAV is always read in process, and with the `native-audio`
+// feature so are the other common formats; anything else (e.g. Opus) goes through ffmpeg.
+fn decode_to_pcm(input: &[u8]) -> WhisperResult<Vec<f32>> {
+    if let Ok(audio) = hudagents_local::audio::parse_wav(input) {
+        return Ok(audio.to_whisper_pcm());
+    }
+    #[cfg(feature = "native-audio")]
+    match hudagents_local::audio::decode_for_whisper(input) {
+        Ok(samples) => return Ok(samples),
+        Err(HAAudioError::Unsupported(_)) => {}
+        Err(e) => return Err(HAWhisperError::DecodeFailed(e.to_string())),
+    }
+    ensure_ffmpeg_installed_once()?;
+    decode_m4a_to_f32(input)
+}
+
+// WAV encodings the in-process reader does not know (e.g. μ-law or ADPCM) go through the
+// same decoders as any other audio.
+fn wav_to_pcm(input: &[u8]) -> WhisperResult<Vec<f32>> {
+    match hudagents_local::audio::parse_wav(input) {
+        Ok(audio) => Ok(audio.to_whisper_pcm()),
+        Err(HAAudioError::Unsupported(_)) => decode_to_pcm(input),
+        Err(e) => Err(HAWhisperError::DecodeFailed(e.to_string())),
+    }
+}
+
 // TODO: Use Thread Pool for ffmpeg decoding to improve performance on multiple
 fn decode_m4a_to_f32(input: &[u8]) -> WhisperResult<Vec<f32>> {
     let mut child = Command::new("ffmpeg")
@@ -113,30 +326,40 @@ fn decode_m4a_to_f32(input: &[u8]) -> WhisperResult<Vec<f32>> {
     Ok(pcm_f32)
 }
 
-pub fn transcribe(input: &[u8], whisper_ctx: &WhisperContext) -> WhisperResult<String> {
+pub fn transcribe(
+    input: &[u8],
+    whisper_ctx: &WhisperContext,
+    options: &TranscribeOptions,
+) -> WhisperResult<Transcript> {
+    transcribe_pcm(&decode_to_pcm(input)?, whisper_ctx, options)
+}
+
+// Whisper reports times in centiseconds.
+fn centiseconds(t: i64) -> Duration {
+    Duration::from_millis(t.max(0) as u64 * 10)
+}
+
+// Expects 16 kHz mono samples.
+pub fn transcribe_pcm(
+    pcm_samples: &[f32],
+    whisper_ctx: &WhisperContext,
+    options: &TranscribeOptions,
+) -> WhisperResult<Transcript> {
     let mut state = whisper_ctx
         .create_state()
         .map_err(|e| HAWhisperError::ModelInitFailed(format!("Error creating state: {:?}", e)))?;
 
-    let n_threads = std::thread::available_parallelism()
-        .map(|n| n.get())
-        .unwrap_or(1);
-
-    let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
-    params.set_n_threads(n_threads as i32);
-    params.set_translate(false);
-    params.set_language(Some("en"));
-    params.set_print_special(false);
-    params.set_print_progress(false);
-    params.set_print_realtime(false);
-    params.set_print_timestamps(false);
+    let (language, language_probability) =
+        resolve_language(&mut state, whisper_ctx, pcm_samples, options)?;
+    let mut params = options.full_params();
+    params.set_language(Some(&language));
 
-    let pcm_samples = decode_m4a_to_f32(input)?;
-
-    state.full(params, &pcm_samples).map_err(|e| {
+    state.full(params, pcm_samples).map_err(|e| {
         HAWhisperError::ModelInitFailed(format!("Error during transcription: {:?}", e))
     })?;
-    let mut transcript = String::new();
+    // Special tokens (end of text, timestamps, ...) sort after the text tokens.
+    let eot = whisper_ctx.token_eot();
+    let mut segments = Vec::new();
     for segment in state.as_iter() {
         let segment_text = segment.to_str_lossy().map_err(|e| {
             HAWhisperError::TranscriptionFailed(format!(
@@ -144,10 +367,114 @@ pub fn transcribe(input: &[u8], whisper_ctx: &WhisperContext) -> WhisperResult<S
                 segment, e
             ))
         })?;
-        transcript.push_str(&segment_text);
-        transcript.push(' ');
+        let mut tokens = Vec::new();
+        for token in (0..segment.n_tokens()).filter_map(|i| segment.get_token(i)) {
+            if token.token_id() >= eot {
+                continue;
+            }
+            let text = token.to_str_lossy().map_err(|e| {
+                HAWhisperError::TranscriptionFailed(format!(
+                    "Error retrieving token text for segment {}: {:?}",
+                    segment, e
+                ))
+            })?;
+            let data = token.token_data();
+            tokens.push(TranscriptWord {
+                start: centiseconds(data.t0),
+                end: centiseconds(data.t1),
+                text: text.into_owned(),
+                probability: token.token_probability(),
+            });
+        }
+        let avg_probability = match tokens.len() {
+            0 => 0.0,
+            n => tokens.iter().map(|t| t.probability).sum::<f32>() / n as f32,
+        };
+        segments.push(TranscriptSegment {
+            start: centiseconds(segment.start_timestamp()),
+            end: centiseconds(segment.end_timestamp()),
+            text: segment_text.trim().to_string(),
+            avg_probability,
+            no_speech_probability: segment.no_speech_probability(),
+            words: if options.word_timestamps {
+                group_words(tokens)
+            } else {
+                Vec::new()
+            },
+        });
     }
-    Ok(transcript.trim().to_string())
+    Ok(Transcript::new(segments).with_language(language, language_probability))
+}
+
+// The language to transcribe in and, when it was detected, whisper's probability for it (over
+// all languages, not just the allowed ones).
+fn resolve_language(
+    state: &mut WhisperState,
+    whisper_ctx: &WhisperContext,
+    pcm_samples: &[f32],
+    options: &TranscribeOptions,
+) -> WhisperResult<(String, Option<f32>)> {
+    if let Some(language) = &options.language {
+        return Ok((language.clone(), None));
+    }
+    // `.en` models only know English.
+    if !whisper_ctx.is_multilingual() {
+        if !allows_english(options) {
+            return Err(HAWhisperError::TranscriptionFailed(format!(
+                "English-only model, but the allowed languages are {:?}",
+                options.allowed_languages
+            )));
+        }
+        return Ok(("en".to_string(), None));
+    }
+    let candidates: Vec<i32> = if options.allowed_languages.is_empty() {
+        (0..=whisper_rs::get_lang_max_id()).collect()
+    } else {
+        let known: Vec<i32> = options
+            .allowed_languages
+            .iter()
+            .filter_map(|language| whisper_rs::get_lang_id(language))
+            .collect();
+        match known.as_slice() {
+            [] => {
+                return Err(HAWhisperError::TranscriptionFailed(format!(
+                    "none of the allowed languages {:?} is known to whisper",
+                    options.allowed_languages
+                )));
+            }
+            [only] => return Ok((language_code(*only)?, None)),
+            _ => known,
+        }
+    };
+
+    let threads = options.threads().max(1);
+    state.pcm_to_mel(pcm_samples, threads).map_err(|e| {
+        HAWhisperError::TranscriptionFailed(format!("Error computing mel spectrogram: {:?}", e))
+    })?;
+    let (_, probabilities) = state.lang_detect(0, threads).map_err(|e| {
+        HAWhisperError::TranscriptionFailed(format!("Error detecting language: {:?}", e))
+    })?;
+    let (id, probability) = most_probable(&probabilities, candidates).ok_or_else(|| {
+        HAWhisperError::TranscriptionFailed("language detection returned nothing".to_string())
+    })?;
+    Ok((language_code(id)?, Some(probability)))
+}
+
+fn language_code(id: i32) -> WhisperResult<String> {
+    whisper_rs::get_lang_str(id)
+        .map(str::to_string)
+        .ok_or_else(|| HAWhisperError::TranscriptionFailed(format!("unknown language id {id}")))
+}
+
+// The candidate language id with the highest probability.
+fn most_probable(
+    probabilities: &[f32],
+    candidates: impl IntoIterator<Item = i32>,
+) -> Option<(i32, f32)> {
+    candidates
+        .into_iter()
+        .filter_map(|id| Some((id, *probabilities.get(usize::try_from(id).ok()?)?)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
 }
 
 pub fn levenshtein(a: &str, b: &str) -> usize {
@@ -227,6 +554,140 @@ mod tests {
         assert!(!pcm_f32.is_empty());
     }
 
+    #[cfg(feature = "native-audio")]
+    #[test]
+    fn test_decode_to_pcm_native_m4a() {
+        let input_data = include_bytes!("test_data/good-m4a.m4a");
+        let native = hudagents_local::audio::decode(input_data).unwrap();
+        let pcm_f32 = decode_to_pcm(input_data).unwrap();
+        let expected =
+            native.samples.len() / native.channels * 16_000 / native.sample_rate as usize;
+        assert!(pcm_f32.len().abs_diff(expected) <= 1);
+    }
+
+    #[cfg(feature = "native-audio")]
+    #[test]
+    fn test_wav_to_pcm_falls_back_for_mu_law() {
+        // WAVE_FORMAT_MULAW (7), 8 kHz mono; 0xff is silence.
+        let mut wav = b"RIFF\x2e\x00\x00\x00WAVEfmt \x12\x00\x00\x00".to_vec();
+        wav.extend_from_slice(&[
+            7, 0, 1, 0, 0x40, 0x1f, 0, 0, 0x40, 0x1f, 0, 0, 1, 0, 8, 0, 0, 0,
+        ]);
+        wav.extend_from_slice(b"data\x08\x00\x00\x00");
+        wav.extend_from_slice(&[0xff; 8]);
+        assert!(matches!(
+            hudagents_local::audio::parse_wav(&wav),
+            Err(HAAudioError::Unsupported(_))
+        ));
+        let pcm = wav_to_pcm(&wav).unwrap();
+        assert_eq!(pcm.len(), 16);
+        assert!(pcm.iter().all(|s| s.abs() < 1e-3));
+    }
+
+    #[test]
+    fn test_decode_to_pcm_reads_wav_without_ffmpeg() {
+        let mut wav = b"RIFF\x2c\x00\x00\x00WAVEfmt \x10\x00\x00\x00".to_vec();
+        wav.extend_from_slice(&[1, 0, 1, 0, 0x80, 0x3e, 0, 0, 0, 0x7d, 0, 0, 2, 0, 16, 0]);
+        wav.extend_from_slice(b"data\x04\x00\x00\x00\x00\x40\x00\xc0");
+        assert_eq!(decode_to_pcm(&wav).unwrap(), vec![0.5, -0.5]);
+    }
+
+    #[cfg(feature = "native-audio")]
+    #[test]
+    fn test_decode_for_whisper_reads_m4a() {
+        // 121 AAC frames of 1024 samples at 48 kHz, mono.
+        let input_data = include_bytes!("test_data/good-m4a.m4a");
+        let pcm = hudagents_local::audio::decode_for_whisper(input_data).unwrap();
+        assert_eq!(pcm.len(), (121 * 1024) / 3);
+
+        // Quiet speech: clearly above silence, nowhere near clipping.
+        let rms = (pcm.iter().map(|s| s * s).sum::<f32>() / pcm.len() as f32).sqrt();
+        let peak = pcm.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!((0.005..0.05).contains(&rms), "{rms}");
+        assert!((0.05..0.5).contains(&peak), "{peak}");
+    }
+
+    #[test]
+    fn test_speech_pcm_drops_silent_input() {
+        let vad = EnergyVad::default();
+        let silence = vec![0.0f32; 16_000];
+        assert!(
+            speech_pcm(Some(&vad), Cow::Borrowed(&silence))
+                .unwrap()
+                .is_none()
+        );
+        // Without a detector everything goes to whisper.
+        assert!(speech_pcm(None, Cow::Borrowed(&silence)).unwrap().is_some());
+
+        let mut speech = silence.clone();
+        speech.extend((0..8_000).map(|i| 0.3 * (i as f32 * 0.08).sin()));
+        speech.extend_from_slice(&silence);
+        let (trimmed, spans) = speech_pcm(Some(&vad), Cow::Borrowed(&speech))
+            .unwrap()
+            .unwrap();
+        assert!(trimmed.len() < 16_000);
+        assert_eq!(spans.len(), 1);
+    }
+
+    #[test]
+    fn test_source_time_maps_back_across_spans() {
+        let span = |start_ms: usize, end_ms: usize| SpeechSpan {
+            start: start_ms * 16,
+            end: end_ms * 16,
+        };
+        let spans = [span(1_000, 2_000), span(5_000, 5_500)];
+        let ms = Duration::from_millis;
+
+        assert_eq!(source_time(&spans, ms(0)), ms(1_000));
+        assert_eq!(source_time(&spans, ms(999)), ms(1_999));
+        assert_eq!(source_time(&spans, ms(1_200)), ms(5_200));
+        // Past the end stays in the last span's frame of reference.
+        assert_eq!(source_time(&spans, ms(1_600)), ms(5_600));
+    }
+
+    #[test]
+    fn test_most_probable_respects_candidates() {
+        let probabilities = [0.6, 0.05, 0.3, 0.05];
+        assert_eq!(most_probable(&probabilities, 0..4), Some((0, 0.6)));
+        assert_eq!(most_probable(&probabilities, [1, 2]), Some((2, 0.3)));
+        // Ids outside the table are ignored.
+        assert_eq!(most_probable(&probabilities, [-1, 9]), None);
+    }
+
+    #[test]
+    fn test_user_options_apply_allowed_languages() {
+        let options = TranscribeOptions::new();
+        let users = HashMap::from([(UserId(7), vec!["ro".to_string(), "es".to_string()])]);
+
+        let tester = user_options(&options, &users, &UserId(7));
+        assert_eq!(tester.language, None);
+        assert_eq!(tester.allowed_languages, ["ro", "es"]);
+        assert!(matches!(
+            user_options(&options, &users, &UserId(1)),
+            Cow::Borrowed(o) if o.language.as_deref() == Some("en")
+        ));
+    }
+
+    #[test]
+    fn test_check_languages_rejects_english_model_without_english() {
+        let options = TranscribeOptions::new().with_allowed_languages(["ro", "es"]);
+        assert!(matches!(
+            check_languages(false, &options),
+            Err(HAAgentError::InvalidConfig(msg)) if msg.contains("\"ro\"")
+        ));
+        assert!(check_languages(true, &options).is_ok());
+        assert!(check_languages(false, &TranscribeOptions::new()).is_ok());
+        let options = TranscribeOptions::new().with_allowed_languages(["ro", "EN"]);
+        assert!(check_languages(false, &options).is_ok());
+    }
+
+    #[test]
+    fn test_check_pcm_rejects_undeclared_format() {
+        assert!(check_pcm(&PcmAudio::i16(vec![0i16; 4], 16_000, 1)).is_ok());
+        assert!(check_pcm(&PcmAudio::i16(vec![0i16; 4], 0, 1)).is_err());
+        assert!(check_pcm(&PcmAudio::f32(vec![0.0; 4], 16_000, 0)).is_err());
+    }
+
     #[test]
     fn test_decode_m4a_to_f32_corrupt_input() {
         let input_data = include_bytes!("test_data/bad-m4a.m4a");
@@ -255,10 +716,12 @@ mod tests {
         let whisper = HALocalWhisper::new(&model_path)
             .expect("Model should be available when HA_WHISPER_PATH is set");
         let input_data = include_bytes!("test_data/good-m4a.m4a");
-        let transcript = transcribe(input_data, &whisper.whisper_ctx)
+        let options = TranscribeOptions::new().with_word_timestamps(true);
+        let transcript = transcribe(input_data, &whisper.whisper_ctx, &options)
             .expect("transcription should succeed with test audio");
         assert!(!transcript.is_empty());
-        println!("Transcript: {}", transcript);
+        assert!(transcript.segments.iter().all(|s| !s.words.is_empty()));
+        println!("Transcript: {}", transcript.text);
     }
 
     // TODO: Move this when the main function is moved
@@ -0,0 +1,213 @@
+// Whisper decoding parameters. The defaults match what the agent always used: greedy decoding,
+// English, no translation and one thread per core.
+use whisper_rs::{FullParams, SamplingStrategy};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Decoding {
+    // Keeps the best of `best_of` samples when temperature fallback kicks in.
+    Greedy { best_of: u32 },
+    BeamSearch { beam_size: u32, patience: f32 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TranscribeOptions {
+    pub decoding: Decoding,
+    pub temperature: f32,
+    // When a segment fails whisper's quality checks it is decoded again at a temperature this
+    // much higher, up to 1.0. 0.0 disables the fallback.
+    pub temperature_increment: f32,
+    // ISO 639-1 code; `None` lets whisper detect the language (multilingual models only).
+    pub language: Option<String>,
+    // Restricts detection to these languages. Empty allows every language whisper knows.
+    pub allowed_languages: Vec<String>,
+    // Translate the speech to English instead of transcribing it.
+    pub translate: bool,
+    // Text the model sees as preceding context, e.g. names and jargon to expect.
+    pub initial_prompt: Option<String>,
+    // Segments whose no-speech probability is above this are treated as silence.
+    pub no_speech_threshold: f32,
+    // Maximum segment length in characters; `None` leaves segmentation to whisper.
+    pub max_segment_chars: Option<usize>,
+    // `None` uses one thread per available core.
+    pub threads: Option<usize>,
+    // Adds word-level timing to every segment, at some extra decoding cost.
+    pub word_timestamps: bool,
+}
+
+impl TranscribeOptions {
+    pub fn new() -> Self {
+        Self {
+            decoding: Decoding::Greedy { best_of: 1 },
+            // whisper.cpp's own defaults.
+            temperature: 0.0,
+            temperature_increment: 0.2,
+            language: Some("en".to_string()),
+            allowed_languages: Vec::new(),
+            translate: false,
+            initial_prompt: None,
+            no_speech_threshold: 0.6,
+            max_segment_chars: None,
+            threads: None,
+            word_timestamps: false,
+        }
+    }
+
+    pub fn with_greedy(mut self, best_of: u32) -> Self {
+        self.decoding = Decoding::Greedy { best_of };
+        self
+    }
+
+    pub fn with_beam_search(mut self, beam_size: u32, patience: f32) -> Self {
+        self.decoding = Decoding::BeamSearch {
+            beam_size,
+            patience,
+        };
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: f32, increment: f32) -> Self {
+        self.temperature = temperature;
+        self.temperature_increment = increment;
+        self
+    }
+
+    pub fn with_language(mut self, language: impl Into<String>) -> Self {
+        self.language = Some(language.into());
+        self
+    }
+
+    pub fn with_auto_language(mut self) -> Self {
+        self.language = None;
+        self
+    }
+
+    // Detects the language, choosing only among `languages`.
+    pub fn with_allowed_languages<S: Into<String>>(
+        mut self,
+        languages: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.language = None;
+        self.allowed_languages = languages.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_translate(mut self, translate: bool) -> Self {
+        self.translate = translate;
+        self
+    }
+
+    pub fn with_initial_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.initial_prompt = Some(prompt.into());
+        self
+    }
+
+    pub fn with_no_speech_threshold(mut self, threshold: f32) -> Self {
+        self.no_speech_threshold = threshold;
+        self
+    }
+
+    pub fn with_max_segment_chars(mut self, chars: usize) -> Self {
+        self.max_segment_chars = Some(chars);
+        self
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    pub fn with_word_timestamps(mut self, enabled: bool) -> Self {
+        self.word_timestamps = enabled;
+        self
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        })
+    }
+
+    fn sampling_strategy(&self) -> SamplingStrategy {
+        match self.decoding {
+            Decoding::Greedy { best_of } => SamplingStrategy::Greedy {
+                best_of: best_of.max(1) as i32,
+            },
+            Decoding::BeamSearch {
+                beam_size,
+                patience,
+            } => SamplingStrategy::BeamSearch {
+                beam_size: beam_size.max(1) as i32,
+                patience,
+            },
+        }
+    }
+
+    pub(crate) fn full_params(&self) -> FullParams<'_, '_> {
+        let mut params = FullParams::new(self.sampling_strategy());
+        params.set_n_threads(self.threads().max(1) as i32);
+        params.set_translate(self.translate);
+        params.set_language(self.language.as_deref());
+        params.set_temperature(self.temperature);
+        params.set_temperature_inc(self.temperature_increment);
+        params.set_no_speech_thold(self.no_speech_threshold);
+        if let Some(prompt) = &self.initial_prompt {
+            params.set_initial_prompt(prompt);
+        }
+        // whisper.cpp only splits on length with token timestamps on.
+        params.set_token_timestamps(self.word_timestamps || self.max_segment_chars.is_some());
+        if let Some(chars) = self.max_segment_chars {
+            params.set_max_len(chars as i32);
+            params.set_split_on_word(true);
+        }
+        params.set_print_special(false);
+        params.set_print_progress(false);
+        params.set_print_realtime(false);
+        params.set_print_timestamps(false);
+        params
+    }
+}
+
+impl Default for TranscribeOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_match_previous_behaviour() {
+        let options = TranscribeOptions::default();
+        assert_eq!(options.decoding, Decoding::Greedy { best_of: 1 });
+        assert_eq!(options.language.as_deref(), Some("en"));
+        assert!(!options.translate);
+        assert!(options.threads() >= 1);
+    }
+
+    #[test]
+    fn builder_sets_decoding_and_language() {
+        let options = TranscribeOptions::new()
+            .with_beam_search(5, 1.0)
+            .with_auto_language()
+            .with_translate(true)
+            .with_threads(2);
+        assert_eq!(
+            options.decoding,
+            Decoding::BeamSearch {
+                beam_size: 5,
+                patience: 1.0
+            }
+        );
+        assert_eq!(options.language, None);
+        assert!(options.translate);
+        assert_eq!(options.threads(), 2);
+
+        let options = TranscribeOptions::new().with_allowed_languages(["ro", "es"]);
+        assert_eq!(options.language, None);
+        assert_eq!(options.allowed_languages, ["ro", "es"]);
+    }
+}
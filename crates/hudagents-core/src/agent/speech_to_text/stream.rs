@@ -0,0 +1,386 @@
+// Live transcription: whisper runs over a sliding window of the incoming PCM. While a window
+// fills up it is re-transcribed every `step` and reported as a partial result; once full it is
+// reported as final and only the last `overlap` of audio is carried into the next window, so
+// words cut at the boundary are heard in full. Text repeated from that overlap is dropped, but
+// never more words than the overlap's share of the previous window held, so a word the speaker
+// really did repeat ("no no") survives.
+use super::{PcmAudio, WhisperResult};
+use hudagents_local::audio::WHISPER_SAMPLE_RATE;
+use std::{collections::VecDeque, mem, time::Duration};
+
+// Transcribes one window of 16 kHz mono samples.
+pub type WindowTranscriber<'a> = Box<dyn FnMut(&[f32]) -> WhisperResult<String> + Send + 'a>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TranscriptEvent {
+    // The transcript of the window so far; later events may still revise it.
+    Partial(String),
+    // Settled text, never repeated by later events.
+    Final(String),
+}
+
+impl TranscriptEvent {
+    pub fn text(&self) -> &str {
+        match self {
+            TranscriptEvent::Partial(text) | TranscriptEvent::Final(text) => text,
+        }
+    }
+
+    pub fn is_final(&self) -> bool {
+        matches!(self, TranscriptEvent::Final(_))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StreamConfig {
+    pub window: Duration,
+    pub step: Duration,
+    pub overlap: Duration,
+}
+
+impl StreamConfig {
+    pub fn new() -> Self {
+        Self {
+            window: Duration::from_secs(10),
+            step: Duration::from_secs(1),
+            overlap: Duration::from_secs(1),
+        }
+    }
+
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn with_step(mut self, step: Duration) -> Self {
+        self.step = step;
+        self
+    }
+
+    // Capped at half the window.
+    pub fn with_overlap(mut self, overlap: Duration) -> Self {
+        self.overlap = overlap;
+        self
+    }
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn samples(duration: Duration) -> usize {
+    (duration.as_secs_f64() * f64::from(WHISPER_SAMPLE_RATE)).round() as usize
+}
+
+pub struct StreamingTranscriber<'a> {
+    transcribe: WindowTranscriber<'a>,
+    window: usize,
+    step: usize,
+    overlap: usize,
+    buffer: Vec<f32>,
+    // Samples pushed since the window was last transcribed.
+    undecoded: usize,
+    last_final: String,
+    // Words of `last_final`'s window that fell into the carried overlap, rounded up.
+    overlap_words: usize,
+    last_partial: String,
+}
+
+impl<'a> StreamingTranscriber<'a> {
+    pub fn new(
+        config: StreamConfig,
+        transcribe: impl FnMut(&[f32]) -> WhisperResult<String> + Send + 'a,
+    ) -> Self {
+        let window = samples(config.window).max(1);
+        Self {
+            transcribe: Box::new(transcribe),
+            window,
+            step: samples(config.step).max(1),
+            overlap: samples(config.overlap).min(window / 2),
+            buffer: Vec::with_capacity(window),
+            undecoded: 0,
+            last_final: String::new(),
+            overlap_words: 0,
+            last_partial: String::new(),
+        }
+    }
+
+    // Takes 16 kHz mono samples and returns the events they produced, if any.
+    pub fn push(&mut self, mut samples: &[f32]) -> WhisperResult<Vec<TranscriptEvent>> {
+        let mut events = Vec::new();
+        while !samples.is_empty() {
+            let take = (self.window - self.buffer.len()).min(samples.len());
+            self.buffer.extend_from_slice(&samples[..take]);
+            self.undecoded += take;
+            samples = &samples[take..];
+            if self.buffer.len() >= self.window {
+                events.extend(self.finalize()?);
+            } else if self.undecoded >= self.step {
+                events.extend(self.partial()?);
+            }
+        }
+        Ok(events)
+    }
+
+    pub fn push_pcm(&mut self, pcm: &PcmAudio) -> WhisperResult<Vec<TranscriptEvent>> {
+        self.push(&pcm.to_whisper_pcm())
+    }
+
+    // Ends the stream: settles whatever is buffered and resets the transcriber for the next one.
+    pub fn finish(&mut self) -> WhisperResult<Option<TranscriptEvent>> {
+        let event = if self.undecoded > 0 {
+            self.finalize()?
+        } else if !self.last_partial.is_empty() {
+            Some(TranscriptEvent::Final(mem::take(&mut self.last_partial)))
+        } else {
+            None
+        };
+        self.buffer.clear();
+        self.last_final.clear();
+        self.overlap_words = 0;
+        Ok(event)
+    }
+
+    // Feeds `chunks` through the transcriber and yields the events, finishing at the end.
+    pub fn stream<I>(self, chunks: I) -> TranscriptStream<'a, I::IntoIter>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[f32]>,
+    {
+        TranscriptStream {
+            transcriber: self,
+            chunks: chunks.into_iter(),
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    // Returns the new text and how many words whisper heard in the whole window.
+    fn decode(&mut self) -> WhisperResult<(String, usize)> {
+        self.undecoded = 0;
+        let text = (self.transcribe)(&self.buffer)?;
+        let words = text.split_whitespace().count();
+        let text = strip_overlap(&self.last_final, text.trim(), self.overlap_words);
+        Ok((text, words))
+    }
+
+    fn partial(&mut self) -> WhisperResult<Option<TranscriptEvent>> {
+        let (text, _) = self.decode()?;
+        if text.is_empty() || text == self.last_partial {
+            return Ok(None);
+        }
+        self.last_partial = text.clone();
+        Ok(Some(TranscriptEvent::Partial(text)))
+    }
+
+    fn finalize(&mut self) -> WhisperResult<Option<TranscriptEvent>> {
+        let (text, words) = self.decode()?;
+        let len = self.buffer.len();
+        let keep_from = len.saturating_sub(self.overlap);
+        self.buffer.drain(..keep_from);
+        self.last_partial.clear();
+        if text.is_empty() {
+            // Nothing new was said, so the next window cannot repeat the last final text.
+            self.last_final.clear();
+            self.overlap_words = 0;
+            return Ok(None);
+        }
+        self.overlap_words = (words * (len - keep_from)).div_ceil(len);
+        self.last_final = text.clone();
+        Ok(Some(TranscriptEvent::Final(text)))
+    }
+}
+
+pub struct TranscriptStream<'a, I> {
+    transcriber: StreamingTranscriber<'a>,
+    chunks: I,
+    pending: VecDeque<TranscriptEvent>,
+    done: bool,
+}
+
+impl<I> Iterator for TranscriptStream<'_, I>
+where
+    I: Iterator,
+    I::Item: AsRef<[f32]>,
+{
+    type Item = WhisperResult<TranscriptEvent>;
+
+    // Stops after the first error.
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+            if self.done {
+                return None;
+            }
+            let events = match self.chunks.next() {
+                Some(chunk) => self.transcriber.push(chunk.as_ref()),
+                None => {
+                    self.done = true;
+                    self.transcriber.finish().map(Vec::from_iter)
+                }
+            };
+            match events {
+                Ok(events) => self.pending.extend(events),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+fn normalize(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+// Drops the words at the start of `next` that repeat the end of `previous`, ignoring case and
+// punctuation. The longest repeat of at most `max_words` wins.
+fn strip_overlap(previous: &str, next: &str, max_words: usize) -> String {
+    let prev: Vec<String> = previous.split_whitespace().map(normalize).collect();
+    let words: Vec<&str> = next.split_whitespace().collect();
+    let repeated = (1..=prev.len().min(words.len()).min(max_words))
+        .rev()
+        .find(|&n| {
+            prev[prev.len() - n..]
+                .iter()
+                .zip(&words[..n])
+                .all(|(a, b)| *a == normalize(b))
+        })
+        .unwrap_or(0);
+    words[repeated..].join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hudagents_local::whisper::HAWhisperError;
+
+    // 1 ms of audio at 16 kHz.
+    const WORD: usize = 16;
+
+    // Every millisecond of audio is one word, named after its sample value.
+    fn fake_whisper(pcm: &[f32]) -> WhisperResult<String> {
+        let words: Vec<String> = pcm
+            .chunks_exact(WORD)
+            .map(|word| format!("w{}", word[0]))
+            .collect();
+        Ok(words.join(" "))
+    }
+
+    fn config() -> StreamConfig {
+        StreamConfig::new()
+            .with_window(Duration::from_millis(10))
+            .with_step(Duration::from_millis(2))
+            .with_overlap(Duration::from_millis(2))
+    }
+
+    #[test]
+    fn stream_emits_partials_and_deduplicated_finals() {
+        let chunks: Vec<Vec<f32>> = (1..=25).map(|i| vec![i as f32; WORD]).collect();
+        let events: Vec<TranscriptEvent> = StreamingTranscriber::new(config(), fake_whisper)
+            .stream(chunks)
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let finals: Vec<&str> = events
+            .iter()
+            .filter(|e| e.is_final())
+            .map(TranscriptEvent::text)
+            .collect();
+        let expected: Vec<String> = (1..=25).map(|i| format!("w{i}")).collect();
+        assert_eq!(finals.join(" "), expected.join(" "));
+        assert_eq!(finals.len(), 3);
+        assert_eq!(events[0], TranscriptEvent::Partial("w1 w2".into()));
+    }
+
+    #[test]
+    fn finish_settles_last_partial() {
+        let mut transcriber = StreamingTranscriber::new(config(), fake_whisper);
+        let events = transcriber.push(&[1.0; 4 * WORD]).unwrap();
+        assert_eq!(events, [TranscriptEvent::Partial("w1 w1 w1 w1".into())]);
+        assert_eq!(
+            transcriber.finish().unwrap(),
+            Some(TranscriptEvent::Final("w1 w1 w1 w1".into()))
+        );
+        assert_eq!(transcriber.finish().unwrap(), None);
+    }
+
+    #[test]
+    fn stream_stops_after_error() {
+        let transcriber = StreamingTranscriber::new(config(), |_: &[f32]| {
+            Err(HAWhisperError::TranscriptionFailed("boom".into()))
+        });
+        let mut stream = transcriber.stream(vec![vec![0.0; 2 * WORD]; 3]);
+        assert!(stream.next().unwrap().is_err());
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn strip_overlap_ignores_case_and_punctuation() {
+        assert_eq!(strip_overlap("so I said, Hello", "hello there", 2), "there");
+        assert_eq!(strip_overlap("a b c", "b c d", 2), "d");
+        assert_eq!(strip_overlap("a b", "c d", 2), "c d");
+        assert_eq!(strip_overlap("", "c d", 2), "c d");
+        assert_eq!(strip_overlap("said no no", "no no thanks", 1), "no thanks");
+    }
+
+    #[test]
+    fn real_repeat_at_the_boundary_survives() {
+        // "w9" is said four times; the first window ends on three of them and only the last
+        // two are carried over as overlap.
+        let values = (1..=7).chain([9, 9, 9, 9]).chain(10..=16);
+        let chunks: Vec<Vec<f32>> = values.map(|i| vec![i as f32; WORD]).collect();
+        let events: Vec<TranscriptEvent> = StreamingTranscriber::new(config(), fake_whisper)
+            .stream(chunks)
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let finals: Vec<&str> = events
+            .iter()
+            .filter(|e| e.is_final())
+            .map(TranscriptEvent::text)
+            .collect();
+        assert_eq!(
+            finals.join(" "),
+            "w1 w2 w3 w4 w5 w6 w7 w9 w9 w9 w9 w10 w11 w12 w13 w14 w15 w16"
+        );
+    }
+
+    #[test]
+    fn silent_window_forgets_the_last_final() {
+        // Silence is heard as nothing. After a fully silent window the speaker repeats the last
+        // two words of the first one, which must not be taken for overlap.
+        let quiet_whisper = |pcm: &[f32]| {
+            fake_whisper(pcm).map(|text| {
+                text.split(' ')
+                    .filter(|w| *w != "w0")
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+        };
+        let values = (1..=8).chain([0; 10]).chain([7, 8]).chain(20..=25);
+        let chunks: Vec<Vec<f32>> = values.map(|i| vec![i as f32; WORD]).collect();
+        let events: Vec<TranscriptEvent> = StreamingTranscriber::new(config(), quiet_whisper)
+            .stream(chunks)
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let finals: Vec<&str> = events
+            .iter()
+            .filter(|e| e.is_final())
+            .map(TranscriptEvent::text)
+            .collect();
+        assert_eq!(
+            finals,
+            ["w1 w2 w3 w4 w5 w6 w7 w8", "w7 w8 w20 w21 w22 w23 w24 w25"]
+        );
+    }
+}
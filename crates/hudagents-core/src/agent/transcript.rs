@@ -0,0 +1,208 @@
+// Structured speech-to-text output: the text plus whisper's segments, their timing and how sure
+// the model was of them.
+use std::time::Duration;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Transcript {
+    pub text: String,
+    pub segments: Vec<TranscriptSegment>,
+    // ISO 639-1 code of the spoken language, when known.
+    pub language: Option<String>,
+    // Whisper's probability for `language`; `None` when the language was given, not detected.
+    pub language_probability: Option<f32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TranscriptSegment {
+    pub start: Duration,
+    pub end: Duration,
+    pub text: String,
+    // Mean probability of the segment's text tokens, 0.0..=1.0.
+    pub avg_probability: f32,
+    // Whisper's estimate that the segment holds no speech at all.
+    pub no_speech_probability: f32,
+    // Empty unless word timestamps were requested.
+    pub words: Vec<TranscriptWord>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TranscriptWord {
+    pub start: Duration,
+    pub end: Duration,
+    pub text: String,
+    pub probability: f32,
+}
+
+impl Transcript {
+    // The text is the segment texts joined by spaces.
+    pub fn new(segments: Vec<TranscriptSegment>) -> Self {
+        let text = segments
+            .iter()
+            .map(|segment| segment.text.trim())
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        Self {
+            text,
+            segments,
+            language: None,
+            language_probability: None,
+        }
+    }
+
+    pub fn with_language(mut self, language: impl Into<String>, probability: Option<f32>) -> Self {
+        self.language = Some(language.into());
+        self.language_probability = probability;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    // Average token probability over the whole transcript, weighting every segment by its
+    // length. `None` without segments, e.g. for a transcript built from plain text.
+    pub fn confidence(&self) -> Option<f32> {
+        if self.segments.is_empty() {
+            return None;
+        }
+        let total: f32 = self
+            .segments
+            .iter()
+            .map(|s| s.end.saturating_sub(s.start).as_secs_f32())
+            .sum();
+        if total <= 0.0 {
+            let sum: f32 = self.segments.iter().map(|s| s.avg_probability).sum();
+            return Some(sum / self.segments.len() as f32);
+        }
+        let weighted: f32 = self
+            .segments
+            .iter()
+            .map(|s| s.avg_probability * s.end.saturating_sub(s.start).as_secs_f32())
+            .sum();
+        Some(weighted / total)
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.segments.last().map_or(Duration::ZERO, |s| s.end)
+    }
+
+    // Rewrites every segment and word time, e.g. to map times in trimmed audio back onto the
+    // original recording.
+    pub fn map_times(&mut self, f: impl Fn(Duration) -> Duration) {
+        for segment in &mut self.segments {
+            segment.start = f(segment.start);
+            segment.end = f(segment.end);
+            for word in &mut segment.words {
+                word.start = f(word.start);
+                word.end = f(word.end);
+            }
+        }
+    }
+}
+
+impl From<String> for Transcript {
+    fn from(text: String) -> Self {
+        Self {
+            text,
+            ..Self::default()
+        }
+    }
+}
+
+impl From<&str> for Transcript {
+    fn from(text: &str) -> Self {
+        Self::from(text.to_string())
+    }
+}
+
+// Whisper tokens carry their leading space, so a token starting with whitespace begins a new
+// word and anything else continues the previous one.
+pub(crate) fn group_words(tokens: impl IntoIterator<Item = TranscriptWord>) -> Vec<TranscriptWord> {
+    let mut words: Vec<TranscriptWord> = Vec::new();
+    let mut pieces = 0;
+    for token in tokens {
+        let starts_word = token.text.starts_with(char::is_whitespace);
+        match words.last_mut() {
+            Some(word) if !starts_word => {
+                word.text.push_str(&token.text);
+                word.end = token.end;
+                pieces += 1;
+                // Running mean over the word's tokens.
+                word.probability += (token.probability - word.probability) / pieces as f32;
+            }
+            _ => {
+                let text = token.text.trim_start().to_string();
+                words.push(TranscriptWord { text, ..token });
+                pieces = 1;
+            }
+        }
+    }
+    words.retain(|word| !word.text.trim().is_empty());
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn segment(start: u64, end: u64, text: &str, p: f32) -> TranscriptSegment {
+        TranscriptSegment {
+            start: ms(start),
+            end: ms(end),
+            text: text.into(),
+            avg_probability: p,
+            ..Default::default()
+        }
+    }
+
+    fn token(start: u64, end: u64, text: &str, probability: f32) -> TranscriptWord {
+        TranscriptWord {
+            start: ms(start),
+            end: ms(end),
+            text: text.into(),
+            probability,
+        }
+    }
+
+    #[test]
+    fn new_joins_text_and_weights_confidence_by_length() {
+        let transcript = Transcript::new(vec![
+            segment(0, 3000, " Hey Solia,", 0.9),
+            segment(3000, 4000, " what is this? ", 0.5),
+        ]);
+        assert_eq!(transcript.text, "Hey Solia, what is this?");
+        assert!((transcript.confidence().unwrap() - 0.8).abs() < 1e-6);
+        assert_eq!(transcript.duration(), ms(4000));
+        assert_eq!(Transcript::from("plain").confidence(), None);
+    }
+
+    #[test]
+    fn group_words_merges_sub_word_tokens() {
+        let words = group_words([
+            token(0, 200, " Hey", 0.9),
+            token(200, 400, " Sol", 0.8),
+            token(400, 600, "ia", 0.4),
+            token(600, 700, ",", 0.9),
+        ]);
+        assert_eq!(words.len(), 2);
+        assert_eq!(words[1].text, "Solia,");
+        assert_eq!((words[1].start, words[1].end), (ms(200), ms(700)));
+        assert!((words[1].probability - 0.7).abs() < 1e-6);
+    }
+
+    #[test]
+    fn map_times_shifts_segments_and_words() {
+        let mut transcript = Transcript::new(vec![TranscriptSegment {
+            words: vec![token(100, 200, "hi", 1.0)],
+            ..segment(0, 500, "hi", 1.0)
+        }]);
+        transcript.map_times(|t| t + ms(1000));
+        assert_eq!(transcript.segments[0].start, ms(1000));
+        assert_eq!(transcript.segments[0].words[0].end, ms(1200));
+    }
+}
@@ -1 +1,74 @@
 // Vision agent module
+pub use super::{Agent, AgentInput, AgentOutput, HAAgentError, check_media};
+use crate::context::blob::MediaKind;
+pub use hudagents_local::ollama::{DEFAULT_OLLAMA_URL, HALocalOllama, HAOllamaError};
+use std::borrow::Cow;
+
+pub const DEFAULT_VISION_PROMPT: &str = "Describe what you see in this image.";
+
+// Interprets images with a local vision LLM (e.g. Qwen3-VL) served by Ollama.
+pub struct VisionAgent {
+    id: Cow<'static, str>,
+    ollama: HALocalOllama,
+    model: String,
+    prompt: String,
+}
+
+impl VisionAgent {
+    pub fn new(
+        id: impl Into<Cow<'static, str>>,
+        ollama: HALocalOllama,
+        model: impl Into<String>,
+        prompt: impl Into<String>,
+    ) -> Self {
+        Self {
+            id: id.into(),
+            ollama,
+            model: model.into(),
+            prompt: prompt.into(),
+        }
+    }
+}
+
+impl Agent for VisionAgent {
+    fn id(&self) -> &str {
+        self.id.as_ref()
+    }
+
+    fn call(&self, agent_input: AgentInput) -> Result<AgentOutput, HAAgentError> {
+        match agent_input {
+            AgentInput::Image(blob) => {
+                check_media(&blob, MediaKind::Image)?;
+                let text = self
+                    .ollama
+                    .generate(&self.model, &self.prompt, &[&blob.bytes])?;
+                Ok(AgentOutput::ImageInterpretation(text))
+            }
+            _ => Err(HAAgentError::InvalidInput("expected image input".into())),
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("VisionAgent({}, model={})", self.id, self.model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::blob::Blob;
+
+    #[test]
+    fn call_rejects_audio_before_contacting_ollama() {
+        let agent = VisionAgent::new(
+            "vision",
+            HALocalOllama::new("http://127.0.0.1:9"),
+            "qwen3-vl",
+            DEFAULT_VISION_PROMPT,
+        );
+        let wav = Blob::new(b"RIFF\0\0\0\0WAVEfmt ".to_vec());
+
+        let err = agent.call(AgentInput::Image(wav)).unwrap_err();
+        assert!(matches!(err, HAAgentError::InvalidInput(msg) if msg.contains("audio/wav")));
+    }
+}
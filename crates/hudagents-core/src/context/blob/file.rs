@@ -0,0 +1,404 @@
+use super::{Blob, BlobId, BlobRef, BlobStore, HABlobError, Index, media::known_mime};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Mutex, MutexGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+// Handle journal, replayed by `new`. Lines are `next <ref>`, `+ <ref> <content id> <mime|->`
+// and `- <ref>`; a torn last line from a crash is ignored.
+const JOURNAL: &str = "refs.log";
+
+// Lines of removed handles (their `+` and `-`) the journal may carry before it is rewritten,
+// as long as they also outnumber the live handles.
+const COMPACT_AFTER: usize = 1_024;
+
+#[derive(Debug)]
+struct Inner {
+    index: Index,
+    journal: File,
+    dead_lines: usize,
+}
+
+impl Inner {
+    // Appends a journal line and syncs it, so a snapshot saved after `put` returns never points
+    // at a handle the journal lost in a crash.
+    fn append(&mut self, line: &str) -> io::Result<()> {
+        self.journal.write_all(line.as_bytes())?;
+        self.journal.sync_data()
+    }
+}
+
+// Stores each distinct content once as `<root>/<content id in hex>`. Handles are journaled
+// next to the blobs, so refs issued by an earlier process resolve to the same bytes after a
+// restart and are never reused. Opening a store compacts the journal and deletes blob files no
+// handle points at, and so does a running store once enough handles were removed. MIME types
+// outside `media`'s constants are not kept across restarts.
+#[derive(Debug)]
+pub struct FileBlobStore {
+    root: PathBuf,
+    inner: Mutex<Inner>,
+    // Keeps the temporary files of concurrent writes apart.
+    next_tmp: AtomicU64,
+}
+
+impl FileBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        let index = Self::recover(&root)?;
+        let journal = OpenOptions::new().append(true).open(root.join(JOURNAL))?;
+        Ok(Self {
+            root,
+            inner: Mutex::new(Inner {
+                index,
+                journal,
+                dead_lines: 0,
+            }),
+            next_tmp: AtomicU64::new(0),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn path(&self, id: BlobId) -> PathBuf {
+        blob_path(&self.root, id)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Writes to a temporary file first so readers never see a partial blob.
+    fn write(&self, id: BlobId, bytes: &[u8]) -> io::Result<()> {
+        let n = self.next_tmp.fetch_add(1, Ordering::Relaxed);
+        let tmp = self.root.join(format!("{:016x}.{}.tmp", id.0, n));
+        fs::write(&tmp, bytes)?;
+        fs::rename(&tmp, self.path(id))
+    }
+
+    // The id of the file holding `bytes`, written when there is none. Files of another length
+    // are passed over without reading them. Runs without the lock.
+    fn store_bytes(&self, bytes: &[u8]) -> io::Result<BlobId> {
+        let mut id = BlobId::of(bytes);
+        loop {
+            let stored = match fs::metadata(self.path(id)) {
+                Ok(meta) if meta.len() != bytes.len() as u64 => None,
+                Ok(_) => Some(fs::read(self.path(id))),
+                Err(e) => Some(Err(e)),
+            };
+            match stored {
+                Some(Ok(stored)) if *stored == *bytes => return Ok(id),
+                Some(Err(e)) if e.kind() == io::ErrorKind::NotFound => {
+                    self.write(id, bytes)?;
+                    return Ok(id);
+                }
+                Some(Err(e)) => return Err(e),
+                _ => id = id.next(),
+            }
+        }
+    }
+
+    // Rewrites the journal with only the live handles.
+    fn compact(&self, inner: &mut Inner) -> io::Result<()> {
+        write_journal(&self.root, &inner.index)?;
+        inner.journal = OpenOptions::new()
+            .append(true)
+            .open(self.root.join(JOURNAL))?;
+        inner.dead_lines = 0;
+        Ok(())
+    }
+
+    // Replays the journal, drops handles whose file is gone, removes unreferenced files and
+    // rewrites the journal with only the live handles.
+    fn recover(root: &Path) -> io::Result<Index> {
+        let (next_ref, handles) = match fs::read_to_string(root.join(JOURNAL)) {
+            Ok(journal) => replay(&journal),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (0, HashMap::new()),
+            Err(e) => return Err(e),
+        };
+        let mut index = Index {
+            next_ref,
+            ..Index::default()
+        };
+        for (blob_ref, (id, mime)) in handles {
+            match fs::metadata(blob_path(root, id)) {
+                Ok(meta) => index.restore(blob_ref, id, meta.len(), mime),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        for entry in fs::read_dir(root)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let orphan = match name.strip_suffix(".tmp") {
+                Some(_) => true,
+                None => u64::from_str_radix(name, 16)
+                    .is_ok_and(|id| name.len() == 16 && !index.blobs.contains_key(&BlobId(id))),
+            };
+            if orphan {
+                fs::remove_file(&path)?;
+            }
+        }
+
+        write_journal(root, &index)?;
+        Ok(index)
+    }
+}
+
+// Replaces the journal atomically with one line per live handle.
+fn write_journal(root: &Path, index: &Index) -> io::Result<()> {
+    let mut journal = format!("next {}\n", index.next_ref);
+    for (blob_ref, handle) in &index.refs {
+        journal.push_str(&add_line(*blob_ref, handle.id, handle.mime));
+    }
+    let path = root.join(JOURNAL);
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(journal.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, &path)
+}
+
+fn blob_path(root: &Path, id: BlobId) -> PathBuf {
+    root.join(format!("{:016x}", id.0))
+}
+
+fn add_line(blob_ref: BlobRef, id: BlobId, mime: Option<&'static str>) -> String {
+    format!("+ {} {:016x} {}\n", blob_ref.0, id.0, mime.unwrap_or("-"))
+}
+
+type Handles = HashMap<BlobRef, (BlobId, Option<&'static str>)>;
+
+// The next free ref and the live handles recorded in `journal`.
+fn replay(journal: &str) -> (u64, Handles) {
+    let mut next_ref = 0;
+    let mut handles = HashMap::new();
+    // Only complete lines; a crash can leave the last one torn.
+    let complete = journal.rfind('\n').map_or("", |end| &journal[..end]);
+    for line in complete.lines() {
+        let mut fields = line.split(' ');
+        match (fields.next(), fields.next().map(str::parse::<u64>)) {
+            (Some("next"), Some(Ok(next))) => next_ref = next_ref.max(next),
+            (Some("+"), Some(Ok(blob_ref))) => {
+                let id = fields
+                    .next()
+                    .and_then(|id| u64::from_str_radix(id, 16).ok());
+                let mime = fields.next().and_then(known_mime);
+                if let Some(id) = id {
+                    handles.insert(BlobRef(blob_ref), (BlobId(id), mime));
+                }
+                next_ref = next_ref.max(blob_ref + 1);
+            }
+            (Some("-"), Some(Ok(blob_ref))) => {
+                handles.remove(&BlobRef(blob_ref));
+            }
+            _ => {}
+        }
+    }
+    (next_ref, handles)
+}
+
+impl BlobStore for FileBlobStore {
+    // Finding or writing the blob file happens outside the lock; only the index update and the
+    // journal line are made under it.
+    fn put(&self, blob: Blob) -> Result<BlobRef, HABlobError> {
+        let id = self.store_bytes(&blob.bytes)?;
+        let mut inner = self.lock();
+        // The last handle to that content may have been removed, and its file with it, since.
+        if !inner.index.blobs.contains_key(&id) && !self.path(id).exists() {
+            self.write(id, &blob.bytes)?;
+        }
+        let blob_ref = inner.index.insert(id, blob.len(), blob.mime);
+        if let Err(e) = inner.append(&add_line(blob_ref, id, blob.mime)) {
+            // Not journaled, so the handle would not survive a restart; undo it.
+            if let Ok(Some(id)) = inner.index.release(blob_ref) {
+                let _ = fs::remove_file(self.path(id));
+            }
+            return Err(e.into());
+        }
+        Ok(blob_ref)
+    }
+
+    fn get(&self, blob_ref: BlobRef) -> Result<Blob, HABlobError> {
+        let handle = self.lock().index.handle(blob_ref)?;
+        let bytes = fs::read(self.path(handle.id))?;
+        Ok(Blob {
+            bytes: bytes.into(),
+            mime: handle.mime,
+        })
+    }
+
+    fn remove(&self, blob_ref: BlobRef) -> Result<(), HABlobError> {
+        let mut inner = self.lock();
+        inner.index.handle(blob_ref)?;
+        inner.append(&format!("- {}\n", blob_ref.0))?;
+        let released = inner.index.release(blob_ref)?;
+        inner.dead_lines += 2;
+        if inner.dead_lines >= COMPACT_AFTER && inner.dead_lines > inner.index.refs.len() {
+            // The `-` line is already durable, so a failed rewrite only delays compaction.
+            let _ = self.compact(&mut inner);
+        }
+        if let Some(id) = released
+            && let Err(e) = fs::remove_file(self.path(id))
+            && e.kind() != io::ErrorKind::NotFound
+        {
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    fn contains(&self, blob_ref: BlobRef) -> bool {
+        self.lock().index.refs.contains_key(&blob_ref)
+    }
+
+    fn len(&self) -> usize {
+        self.lock().index.blobs.len()
+    }
+
+    fn size(&self) -> u64 {
+        self.lock().index.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("hudagents-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        root
+    }
+
+    fn blob_files(root: &Path) -> usize {
+        fs::read_dir(root)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name() != JOURNAL)
+            .count()
+    }
+
+    #[test]
+    fn duplicates_share_one_file() {
+        let root = temp_root("blob-dedup");
+        let store = FileBlobStore::new(&root).unwrap();
+        let first = store
+            .put(Blob::new(vec![5; 32]).with_mime("image/png"))
+            .unwrap();
+        let second = store.put(Blob::new(vec![5; 32])).unwrap();
+
+        assert_eq!(blob_files(&root), 1);
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.size(), 32);
+
+        let got = store.get(first).unwrap();
+        assert_eq!(&got.bytes[..], &[5; 32]);
+        assert_eq!(got.mime, Some("image/png"));
+        assert_eq!(store.get(second).unwrap().mime, None);
+
+        store.remove(first).unwrap();
+        assert_eq!(blob_files(&root), 1);
+        store.remove(second).unwrap();
+        assert_eq!(blob_files(&root), 0);
+        assert!(store.is_empty());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn colliding_ids_keep_both_contents() {
+        let root = temp_root("blob-collision");
+        let store = FileBlobStore::new(&root).unwrap();
+        // Pretend some other content already owns the hash of [1, 2, 3].
+        fs::write(store.path(BlobId::of(&[1, 2, 3])), [9, 9]).unwrap();
+
+        // ... and its next id is taken by different content of the same length.
+        fs::write(store.path(BlobId::of(&[1, 2, 3]).next()), [9, 9, 9]).unwrap();
+
+        let blob_ref = store.put(Blob::new(vec![1, 2, 3])).unwrap();
+        assert_eq!(&store.get(blob_ref).unwrap().bytes[..], &[1, 2, 3]);
+        assert!(store.path(BlobId::of(&[1, 2, 3]).next().next()).exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn refs_survive_a_restart() {
+        let root = temp_root("blob-restart");
+        let store = FileBlobStore::new(&root).unwrap();
+        let image = store
+            .put(Blob::new(vec![4; 8]).with_mime("image/png"))
+            .unwrap();
+        let copy = store.put(Blob::new(vec![4; 8])).unwrap();
+        let audio = store.put(Blob::new(vec![2; 3])).unwrap();
+        store.remove(audio).unwrap();
+        drop(store);
+        // A file no handle points at, e.g. written just before a crash.
+        fs::write(blob_path(&root, BlobId(0xabc)), [1]).unwrap();
+
+        let store = FileBlobStore::new(&root).unwrap();
+        let got = store.get(image).unwrap();
+        assert_eq!(&got.bytes[..], &[4; 8]);
+        assert_eq!(got.mime, Some("image/png"));
+        assert!(store.contains(copy));
+        assert!(!store.contains(audio));
+        assert_eq!((store.len(), store.size()), (1, 8));
+        assert!(!blob_path(&root, BlobId(0xabc)).exists());
+
+        // Refs are never reused, so an old ref cannot resolve to new content.
+        let next = store.put(Blob::new(vec![9])).unwrap();
+        assert!(next.0 > audio.0);
+        assert!(matches!(store.get(audio), Err(HABlobError::NotFound(_))));
+
+        store.remove(image).unwrap();
+        store.remove(copy).unwrap();
+        drop(store);
+        let store = FileBlobStore::new(&root).unwrap();
+        assert_eq!((store.len(), store.size()), (1, 1));
+        assert_eq!(blob_files(&root), 1);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn journal_is_compacted_while_running() {
+        let root = temp_root("blob-compact");
+        let store = FileBlobStore::new(&root).unwrap();
+        let kept = store.put(Blob::new(vec![1; 4])).unwrap();
+        for i in 0..COMPACT_AFTER as u32 {
+            let blob_ref = store.put(Blob::new(i.to_le_bytes().to_vec())).unwrap();
+            store.remove(blob_ref).unwrap();
+        }
+
+        let lines = fs::read_to_string(root.join(JOURNAL))
+            .unwrap()
+            .lines()
+            .count();
+        assert!(lines <= COMPACT_AFTER, "{lines}");
+        drop(store);
+        let store = FileBlobStore::new(&root).unwrap();
+        assert_eq!(&store.get(kept).unwrap().bytes[..], &[1; 4]);
+        assert_eq!(store.len(), 1);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn replay_ignores_torn_last_line() {
+        let (next, handles) =
+            replay("next 4\n+ 4 00000000000000ff audio/wav\n+ 5 00000000000000aa im");
+        assert_eq!(next, 5);
+        assert_eq!(handles[&BlobRef(4)], (BlobId(0xff), Some("audio/wav")));
+        assert!(!handles.contains_key(&BlobRef(5)));
+    }
+}
@@ -0,0 +1,463 @@
+use super::{Blob, BlobRef, BlobStore, HABlobError};
+use crate::context::ids::{RunId, UserId};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+#[derive(Clone, Debug)]
+struct Entry {
+    run: RunId,
+    user: UserId,
+    len: u64,
+    last_used: u64,
+    pinned: bool,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: HashMap<BlobRef, Entry>,
+    usage: HashMap<UserId, u64>,
+    // Each user's evictable blobs by last use, oldest first. Pinned blobs are left out.
+    lru: HashMap<UserId, BTreeMap<u64, BlobRef>>,
+    tick: u64,
+}
+
+impl State {
+    fn admit(&mut self, blob_ref: BlobRef, entry: Entry) {
+        *self.usage.entry(entry.user.clone()).or_default() += entry.len;
+        if !entry.pinned {
+            self.lru
+                .entry(entry.user.clone())
+                .or_default()
+                .insert(entry.last_used, blob_ref);
+        }
+        self.entries.insert(blob_ref, entry);
+    }
+
+    fn unlist(&mut self, entry: &Entry) {
+        if let Some(lru) = self.lru.get_mut(&entry.user) {
+            lru.remove(&entry.last_used);
+            if lru.is_empty() {
+                self.lru.remove(&entry.user);
+            }
+        }
+    }
+
+    fn touch(&mut self, blob_ref: BlobRef) -> Result<(), HABlobError> {
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self
+            .entries
+            .get_mut(&blob_ref)
+            .ok_or(HABlobError::NotFound(blob_ref))?;
+        let (user, last_used, pinned) = (entry.user.clone(), entry.last_used, entry.pinned);
+        entry.last_used = tick;
+        if !pinned && let Some(lru) = self.lru.get_mut(&user) {
+            lru.remove(&last_used);
+            lru.insert(tick, blob_ref);
+        }
+        Ok(())
+    }
+}
+
+// Run-scoped ownership on top of a `BlobStore`. Every blob belongs to the run and user that put
+// it: `finish_run` frees everything a run still holds, and a user over their byte quota loses
+// their least recently used blobs first. Pinned blobs are never evicted for the quota. Messages
+// pointing at an evicted blob resolve to `HABlobError::NotFound`.
+pub struct BlobManager {
+    store: Arc<dyn BlobStore + Send + Sync>,
+    default_quota: Option<u64>,
+    quotas: HashMap<UserId, u64>,
+    state: Mutex<State>,
+}
+
+impl BlobManager {
+    pub fn new(store: Arc<dyn BlobStore + Send + Sync>) -> Self {
+        Self {
+            store,
+            default_quota: None,
+            quotas: HashMap::new(),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    // Byte quota for every user without a quota of their own.
+    pub fn with_default_quota(mut self, bytes: u64) -> Self {
+        self.default_quota = Some(bytes);
+        self
+    }
+
+    pub fn with_user_quota(mut self, user: UserId, bytes: u64) -> Self {
+        self.quotas.insert(user, bytes);
+        self
+    }
+
+    pub fn store(&self) -> &Arc<dyn BlobStore + Send + Sync> {
+        &self.store
+    }
+
+    pub fn quota(&self, user: &UserId) -> Option<u64> {
+        self.quotas.get(user).copied().or(self.default_quota)
+    }
+
+    // Bytes currently held by the user; a blob shared by two handles counts twice.
+    pub fn usage(&self, user: &UserId) -> u64 {
+        self.lock().usage.get(user).copied().unwrap_or(0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Stores a blob owned by `run` and `user`, then evicts the user's least recently used
+    // unpinned blobs until they fit into their quota again; pinned blobs may keep a user above
+    // it. Nothing is evicted when the store fails. The store is only called outside the
+    // bookkeeping lock, so a slow disk does not hold up other blob operations.
+    pub fn put(&self, run: &RunId, user: &UserId, blob: Blob) -> Result<BlobRef, HABlobError> {
+        let len = blob.len() as u64;
+        let quota = self.quota(user);
+        if let Some(quota) = quota
+            && len > quota
+        {
+            return Err(HABlobError::QuotaExceeded {
+                user: user.clone(),
+                bytes: len,
+                quota,
+            });
+        }
+        // Reserved up front so concurrent puts of the same user see it.
+        *self.lock().usage.entry(user.clone()).or_default() += len;
+
+        let stored = self.store.put(blob);
+        let mut evicted = Vec::new();
+        let blob_ref = {
+            let mut state = self.lock();
+            unreserve(&mut state, user, len);
+            let blob_ref = stored?;
+            state.tick += 1;
+            let entry = Entry {
+                run: run.clone(),
+                user: user.clone(),
+                len,
+                last_used: state.tick,
+                pinned: false,
+            };
+            if let Some(quota) = quota {
+                while state.usage.get(user).copied().unwrap_or(0) + len > quota {
+                    let lru = state
+                        .lru
+                        .get(user)
+                        .and_then(|lru| lru.values().next().copied());
+                    let Some(victim) = lru else {
+                        break;
+                    };
+                    let Ok(entry) = forget(&mut state, victim) else {
+                        break;
+                    };
+                    evicted.push((victim, entry));
+                }
+            }
+            state.admit(blob_ref, entry);
+            blob_ref
+        };
+
+        // A victim the store cannot remove stays owned and counted, as it still takes space.
+        for (victim, entry) in evicted {
+            if self.store.remove(victim).is_err() {
+                self.lock().admit(victim, entry);
+            }
+        }
+        Ok(blob_ref)
+    }
+
+    // Reads a blob and marks it as recently used.
+    pub fn get(&self, blob_ref: BlobRef) -> Result<Blob, HABlobError> {
+        self.lock().touch(blob_ref)?;
+        self.store.get(blob_ref)
+    }
+
+    // Keeps a blob out of quota eviction, e.g. while a pinned message refers to it. It is still
+    // freed by `release` and `finish_run`.
+    pub fn pin(&self, blob_ref: BlobRef) -> Result<(), HABlobError> {
+        let mut state = self.lock();
+        let entry = state
+            .entries
+            .get_mut(&blob_ref)
+            .ok_or(HABlobError::NotFound(blob_ref))?;
+        if !entry.pinned {
+            entry.pinned = true;
+            let entry = entry.clone();
+            state.unlist(&entry);
+        }
+        Ok(())
+    }
+
+    pub fn contains(&self, blob_ref: BlobRef) -> bool {
+        self.lock().entries.contains_key(&blob_ref)
+    }
+
+    pub fn release(&self, blob_ref: BlobRef) -> Result<(), HABlobError> {
+        forget(&mut self.lock(), blob_ref)?;
+        self.store.remove(blob_ref)
+    }
+
+    // Frees every blob still owned by the run. Returns how many were released. Every blob is
+    // released even when the store fails on some; the first such error is returned.
+    pub fn finish_run(&self, run: &RunId) -> Result<usize, HABlobError> {
+        let owned: Vec<BlobRef> = {
+            let mut state = self.lock();
+            let owned: Vec<BlobRef> = state
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.run == *run)
+                .map(|(blob_ref, _)| *blob_ref)
+                .collect();
+            for &blob_ref in &owned {
+                forget(&mut state, blob_ref)?;
+            }
+            owned
+        };
+        let released = owned.len();
+        self.remove_all(owned)?;
+        Ok(released)
+    }
+
+    // Removes every blob from the store, returning the first error.
+    fn remove_all(&self, blob_refs: Vec<BlobRef>) -> Result<(), HABlobError> {
+        let mut result = Ok(());
+        for blob_ref in blob_refs {
+            if let Err(e) = self.store.remove(blob_ref)
+                && result.is_ok()
+            {
+                result = Err(e);
+            }
+        }
+        result
+    }
+}
+
+// Drops the bookkeeping of a blob; the caller removes it from the store.
+fn forget(state: &mut State, blob_ref: BlobRef) -> Result<Entry, HABlobError> {
+    let entry = state
+        .entries
+        .remove(&blob_ref)
+        .ok_or(HABlobError::NotFound(blob_ref))?;
+    unreserve(state, &entry.user, entry.len);
+    state.unlist(&entry);
+    Ok(entry)
+}
+
+fn unreserve(state: &mut State, user: &UserId, len: u64) {
+    if let Some(usage) = state.usage.get_mut(user) {
+        *usage -= len;
+        if *usage == 0 {
+            state.usage.remove(user);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::blob::MemoryBlobStore;
+    use std::io;
+
+    // Fails to remove one chosen blob.
+    struct StubbornStore {
+        inner: MemoryBlobStore,
+        stuck: Mutex<Option<BlobRef>>,
+    }
+
+    impl BlobStore for StubbornStore {
+        fn put(&self, blob: Blob) -> Result<BlobRef, HABlobError> {
+            self.inner.put(blob)
+        }
+
+        fn get(&self, blob_ref: BlobRef) -> Result<Blob, HABlobError> {
+            self.inner.get(blob_ref)
+        }
+
+        fn remove(&self, blob_ref: BlobRef) -> Result<(), HABlobError> {
+            if *self.stuck.lock().unwrap() == Some(blob_ref) {
+                return Err(io::Error::other("disk on fire").into());
+            }
+            self.inner.remove(blob_ref)
+        }
+
+        fn contains(&self, blob_ref: BlobRef) -> bool {
+            self.inner.contains(blob_ref)
+        }
+
+        fn len(&self) -> usize {
+            self.inner.len()
+        }
+
+        fn size(&self) -> u64 {
+            self.inner.size()
+        }
+    }
+
+    // Fails every put while `fail` is set.
+    struct FlakyPutStore {
+        inner: MemoryBlobStore,
+        fail: Mutex<bool>,
+    }
+
+    impl BlobStore for FlakyPutStore {
+        fn put(&self, blob: Blob) -> Result<BlobRef, HABlobError> {
+            if *self.fail.lock().unwrap() {
+                return Err(io::Error::other("disk full").into());
+            }
+            self.inner.put(blob)
+        }
+
+        fn get(&self, blob_ref: BlobRef) -> Result<Blob, HABlobError> {
+            self.inner.get(blob_ref)
+        }
+
+        fn remove(&self, blob_ref: BlobRef) -> Result<(), HABlobError> {
+            self.inner.remove(blob_ref)
+        }
+
+        fn contains(&self, blob_ref: BlobRef) -> bool {
+            self.inner.contains(blob_ref)
+        }
+
+        fn len(&self) -> usize {
+            self.inner.len()
+        }
+
+        fn size(&self) -> u64 {
+            self.inner.size()
+        }
+    }
+
+    fn manager() -> (Arc<MemoryBlobStore>, BlobManager) {
+        let store = Arc::new(MemoryBlobStore::new());
+        (store.clone(), BlobManager::new(store))
+    }
+
+    #[test]
+    fn finish_run_frees_only_that_run() {
+        let (store, blobs) = manager();
+        let user = UserId(1);
+        let first = blobs.put(&RunId(1), &user, Blob::new(vec![1; 8])).unwrap();
+        blobs.put(&RunId(1), &user, Blob::new(vec![2; 8])).unwrap();
+        let kept = blobs.put(&RunId(2), &user, Blob::new(vec![3; 8])).unwrap();
+
+        assert_eq!(blobs.usage(&user), 24);
+        assert_eq!(blobs.finish_run(&RunId(1)).unwrap(), 2);
+        assert!(!blobs.contains(first));
+        assert!(blobs.contains(kept));
+        assert_eq!(blobs.usage(&user), 8);
+        assert_eq!(store.size(), 8);
+    }
+
+    #[test]
+    fn quota_evicts_least_recently_used_blob_of_that_user() {
+        let (_, blobs) = manager();
+        let blobs = blobs.with_default_quota(20);
+        let run = RunId(1);
+        let (alice, bob) = (UserId(1), UserId(2));
+
+        let old = blobs.put(&run, &alice, Blob::new(vec![1; 8])).unwrap();
+        let recent = blobs.put(&run, &alice, Blob::new(vec![2; 8])).unwrap();
+        let other = blobs.put(&run, &bob, Blob::new(vec![3; 8])).unwrap();
+        blobs.get(old).unwrap();
+
+        let new = blobs.put(&run, &alice, Blob::new(vec![4; 8])).unwrap();
+        assert!(blobs.contains(old));
+        assert!(!blobs.contains(recent));
+        assert!(blobs.contains(new));
+        assert!(blobs.contains(other));
+        assert_eq!(blobs.usage(&alice), 16);
+        assert!(matches!(blobs.get(recent), Err(HABlobError::NotFound(_))));
+    }
+
+    #[test]
+    fn quota_eviction_skips_pinned_blobs() {
+        let (_, blobs) = manager();
+        let blobs = blobs.with_default_quota(16);
+        let (run, user) = (RunId(1), UserId(1));
+
+        let pinned = blobs.put(&run, &user, Blob::new(vec![1; 8])).unwrap();
+        let loose = blobs.put(&run, &user, Blob::new(vec![2; 8])).unwrap();
+        blobs.pin(pinned).unwrap();
+
+        let new = blobs.put(&run, &user, Blob::new(vec![3; 8])).unwrap();
+        assert!(blobs.contains(pinned));
+        assert!(!blobs.contains(loose));
+        assert!(blobs.contains(new));
+
+        // Only pinned blobs are left to evict, so the user goes over the quota.
+        blobs.pin(new).unwrap();
+        blobs.put(&run, &user, Blob::new(vec![4; 8])).unwrap();
+        assert_eq!(blobs.usage(&user), 24);
+    }
+
+    #[test]
+    fn failed_put_evicts_nothing() {
+        let store = Arc::new(FlakyPutStore {
+            inner: MemoryBlobStore::new(),
+            fail: Mutex::new(false),
+        });
+        let blobs = BlobManager::new(store.clone()).with_default_quota(8);
+        let (run, user) = (RunId(1), UserId(1));
+        let old = blobs.put(&run, &user, Blob::new(vec![1; 8])).unwrap();
+
+        *store.fail.lock().unwrap() = true;
+        assert!(blobs.put(&run, &user, Blob::new(vec![2; 8])).is_err());
+        assert!(blobs.contains(old));
+        assert!(store.contains(old));
+        assert_eq!(blobs.usage(&user), 8);
+    }
+
+    #[test]
+    fn blob_larger_than_quota_is_rejected() {
+        let (store, blobs) = manager();
+        let blobs = blobs.with_user_quota(UserId(7), 4);
+
+        let err = blobs
+            .put(&RunId(1), &UserId(7), Blob::new(vec![0; 5]))
+            .unwrap_err();
+        assert!(matches!(err, HABlobError::QuotaExceeded { quota: 4, .. }));
+        assert!(store.is_empty());
+        assert!(
+            blobs
+                .put(&RunId(1), &UserId(8), Blob::new(vec![0; 5]))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn finish_run_releases_every_blob_despite_store_errors() {
+        let store = Arc::new(StubbornStore {
+            inner: MemoryBlobStore::new(),
+            stuck: Mutex::new(None),
+        });
+        let blobs = BlobManager::new(store.clone());
+        let user = UserId(1);
+        let refs: Vec<BlobRef> = (0..3)
+            .map(|i| blobs.put(&RunId(1), &user, Blob::new(vec![i; 4])).unwrap())
+            .collect();
+        *store.stuck.lock().unwrap() = Some(refs[0]);
+
+        assert!(matches!(
+            blobs.finish_run(&RunId(1)),
+            Err(HABlobError::Io(_))
+        ));
+        assert!(blobs.is_empty());
+        assert_eq!(blobs.usage(&user), 0);
+        assert!(store.contains(refs[0]));
+        assert!(!store.contains(refs[1]) && !store.contains(refs[2]));
+    }
+}
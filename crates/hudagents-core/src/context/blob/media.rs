@@ -0,0 +1,445 @@
+// Magic-byte detection and cheap header parsing for the media we get from the glasses.
+use hudagents_local::audio::is_chunk_header;
+use std::time::Duration;
+
+pub const AUDIO_AAC: &str = "audio/aac";
+pub const AUDIO_FLAC: &str = "audio/flac";
+pub const AUDIO_MP4: &str = "audio/mp4";
+pub const AUDIO_MPEG: &str = "audio/mpeg";
+pub const AUDIO_OGG: &str = "audio/ogg";
+pub const AUDIO_OPUS: &str = "audio/opus";
+pub const AUDIO_WAV: &str = "audio/wav";
+pub const IMAGE_HEIC: &str = "image/heic";
+pub const IMAGE_JPEG: &str = "image/jpeg";
+pub const IMAGE_PNG: &str = "image/png";
+pub const IMAGE_WEBP: &str = "image/webp";
+
+const KNOWN: [&str; 11] = [
+    AUDIO_AAC, AUDIO_FLAC, AUDIO_MP4, AUDIO_MPEG, AUDIO_OGG, AUDIO_OPUS, AUDIO_WAV, IMAGE_HEIC,
+    IMAGE_JPEG, IMAGE_PNG, IMAGE_WEBP,
+];
+
+// The constant for `mime` if it is one of the types above.
+pub fn known_mime(mime: &str) -> Option<&'static str> {
+    KNOWN.into_iter().find(|known| *known == mime)
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MediaKind {
+    Audio,
+    Image,
+}
+
+impl MediaKind {
+    pub fn of(mime: &str) -> Option<Self> {
+        if mime.starts_with("audio/") {
+            Some(MediaKind::Audio)
+        } else if mime.starts_with("image/") {
+            Some(MediaKind::Image)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn sniff_mime(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, ..] => Some(IMAGE_PNG),
+        [0xff, 0xd8, 0xff, ..] => Some(IMAGE_JPEG),
+        [
+            b'R',
+            b'I',
+            b'F',
+            b'F',
+            _,
+            _,
+            _,
+            _,
+            b'W',
+            b'A',
+            b'V',
+            b'E',
+            ..,
+        ] => Some(AUDIO_WAV),
+        [
+            b'R',
+            b'I',
+            b'F',
+            b'F',
+            _,
+            _,
+            _,
+            _,
+            b'W',
+            b'E',
+            b'B',
+            b'P',
+            ..,
+        ] => Some(IMAGE_WEBP),
+        [b'f', b'L', b'a', b'C', ..] => Some(AUDIO_FLAC),
+        [b'O', b'g', b'g', b'S', ..] if bytes.get(28..36) == Some(b"OpusHead") => Some(AUDIO_OPUS),
+        [b'O', b'g', b'g', b'S', ..] => Some(AUDIO_OGG),
+        [b'I', b'D', b'3', ..] => Some(AUDIO_MPEG),
+        // MPEG audio frame sync; layer bits 00 mark an ADTS (AAC) header.
+        [0xff, b1, ..] if b1 & 0xe0 == 0xe0 => {
+            if b1 & 0x06 == 0 {
+                Some(AUDIO_AAC)
+            } else {
+                Some(AUDIO_MPEG)
+            }
+        }
+        [_, _, _, _, b'f', b't', b'y', b'p', b0, b1, b2, b3, ..] => match &[*b0, *b1, *b2, *b3] {
+            b"heic" | b"heix" | b"hevc" | b"hevx" | b"mif1" | b"msf1" => Some(IMAGE_HEIC),
+            _ => Some(AUDIO_MP4),
+        },
+        _ => None,
+    }
+}
+
+// What could be read from the headers without decoding. Fields that do not apply to the
+// format, or that would need a full parse, are `None`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MediaInfo {
+    pub mime: Option<&'static str>,
+    pub duration: Option<Duration>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl MediaInfo {
+    pub fn probe(bytes: &[u8]) -> Self {
+        let mime = sniff_mime(bytes);
+        let mut info = MediaInfo {
+            mime,
+            ..MediaInfo::default()
+        };
+        match mime {
+            Some(AUDIO_WAV) => probe_wav(bytes, &mut info),
+            Some(AUDIO_FLAC) => probe_flac(bytes, &mut info),
+            Some(AUDIO_OPUS) => probe_opus(bytes, &mut info),
+            Some(AUDIO_AAC) => probe_adts(bytes, &mut info),
+            Some(AUDIO_MP4) => info.duration = mp4_duration(bytes),
+            Some(IMAGE_PNG) => {
+                info.width = be_u32(bytes, 16);
+                info.height = be_u32(bytes, 20);
+            }
+            Some(IMAGE_JPEG) => {
+                if let Some((width, height)) = jpeg_dimensions(bytes) {
+                    info.width = Some(width);
+                    info.height = Some(height);
+                }
+            }
+            Some(IMAGE_WEBP) => {
+                if let Some((width, height)) = webp_dimensions(bytes) {
+                    info.width = Some(width);
+                    info.height = Some(height);
+                }
+            }
+            _ => {}
+        }
+        info
+    }
+
+    pub fn kind(&self) -> Option<MediaKind> {
+        self.mime.and_then(MediaKind::of)
+    }
+}
+
+fn be_u16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn be_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn be_u64(bytes: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(bytes.get(at..at + 8)?.try_into().ok()?))
+}
+
+fn le_u16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn le_u64(bytes: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(bytes.get(at..at + 8)?.try_into().ok()?))
+}
+
+// RIFF chunks: `fmt ` gives the format, `data` the payload length.
+fn probe_wav(bytes: &[u8], info: &mut MediaInfo) {
+    let mut at = 12;
+    let mut byte_rate = None;
+    while let (Some(id), Some(len)) = (bytes.get(at..at + 4), le_u32(bytes, at + 4)) {
+        let body = at + 8;
+        match id {
+            b"fmt " => {
+                info.channels = le_u16(bytes, body + 2);
+                info.sample_rate = le_u32(bytes, body + 4);
+                byte_rate = le_u32(bytes, body + 8);
+            }
+            b"data" => {
+                // Streamed WAVs leave the size at u32::MAX, some at 0; fall back to what we
+                // have. A 0 followed by another chunk is a genuinely empty `data` chunk.
+                let rest = bytes.get(body..).unwrap_or_default();
+                let len = match len {
+                    u32::MAX => rest.len() as u64,
+                    0 if !is_chunk_header(rest) => rest.len() as u64,
+                    len => u64::from(len),
+                };
+                if let Some(rate) = byte_rate.filter(|&rate| rate > 0) {
+                    info.duration = Some(Duration::from_secs_f64(len as f64 / f64::from(rate)));
+                }
+                return;
+            }
+            _ => {}
+        }
+        // Saturating: a length near u32::MAX must not wrap the offset on 32-bit targets.
+        let next = body
+            .saturating_add(len as usize)
+            .saturating_add(len as usize & 1);
+        if next <= at || next >= bytes.len() {
+            return;
+        }
+        at = next;
+    }
+}
+
+// STREAMINFO is always the first metadata block.
+fn probe_flac(bytes: &[u8], info: &mut MediaInfo) {
+    let Some(packed) = be_u64(bytes, 18) else {
+        return;
+    };
+    let sample_rate = (packed >> 44) as u32;
+    let channels = ((packed >> 41) & 0x7) as u16 + 1;
+    let total_samples = packed & 0xf_ffff_ffff;
+    info.sample_rate = Some(sample_rate);
+    info.channels = Some(channels);
+    if sample_rate > 0 && total_samples > 0 {
+        info.duration = Some(Duration::from_secs_f64(
+            total_samples as f64 / f64::from(sample_rate),
+        ));
+    }
+}
+
+// Opus always decodes at 48 kHz; the duration is the granule position of the last page minus
+// the pre-skip.
+fn probe_opus(bytes: &[u8], info: &mut MediaInfo) {
+    info.channels = bytes.get(37).map(|&channels| u16::from(channels));
+    info.sample_rate = Some(48_000);
+    let pre_skip = le_u16(bytes, 38).unwrap_or(0);
+    let last_page = bytes.windows(4).rposition(|window| window == b"OggS");
+    if let Some(granule) = last_page.and_then(|at| le_u64(bytes, at + 6)) {
+        let samples = granule.saturating_sub(u64::from(pre_skip));
+        info.duration = Some(Duration::from_secs_f64(samples as f64 / 48_000.0));
+    }
+}
+
+fn probe_adts(bytes: &[u8], info: &mut MediaInfo) {
+    const RATES: [u32; 13] = [
+        96_000, 88_200, 64_000, 48_000, 44_100, 32_000, 24_000, 22_050, 16_000, 12_000, 11_025,
+        8_000, 7_350,
+    ];
+    let (Some(&b2), Some(&b3)) = (bytes.get(2), bytes.get(3)) else {
+        return;
+    };
+    info.sample_rate = RATES.get(usize::from((b2 >> 2) & 0xf)).copied();
+    info.channels = Some(u16::from(((b2 & 0x1) << 2) | (b3 >> 6)));
+}
+
+// Walks the top-level boxes to `moov/mvhd`.
+fn mp4_duration(bytes: &[u8]) -> Option<Duration> {
+    let moov = find_box(bytes, b"moov")?;
+    let mvhd = find_box(moov, b"mvhd")?;
+    let (timescale, duration) = match mvhd.first()? {
+        1 => (be_u32(mvhd, 20)?, be_u64(mvhd, 24)?),
+        _ => (be_u32(mvhd, 12)?, u64::from(be_u32(mvhd, 16)?)),
+    };
+    (timescale > 0).then(|| Duration::from_secs_f64(duration as f64 / f64::from(timescale)))
+}
+
+// Returns the body of the first box of the given type.
+fn find_box<'a>(bytes: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut at = 0;
+    while at + 8 <= bytes.len() {
+        let (header, len) = match be_u32(bytes, at)? {
+            0 => (8, bytes.len() - at),
+            1 => (16, usize::try_from(be_u64(bytes, at + 8)?).ok()?),
+            len => (8, len as usize),
+        };
+        if len < header {
+            return None;
+        }
+        // Checked: a 64-bit size is chosen by the input and must not wrap the offset.
+        let end = at.checked_add(len)?;
+        if bytes.get(at + 4..at + 8)? == kind {
+            return bytes.get(at + header..end.min(bytes.len()));
+        }
+        if end >= bytes.len() {
+            return None;
+        }
+        at = end;
+    }
+    None
+}
+
+// Dimensions live in the first start-of-frame segment.
+fn jpeg_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let mut at = 2;
+    loop {
+        if *bytes.get(at)? != 0xff {
+            return None;
+        }
+        let marker = *bytes.get(at + 1)?;
+        let len = usize::from(be_u16(bytes, at + 2)?);
+        if matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc) {
+            let height = be_u16(bytes, at + 5)?;
+            let width = be_u16(bytes, at + 7)?;
+            return Some((u32::from(width), u32::from(height)));
+        }
+        at += 2 + len;
+    }
+}
+
+fn webp_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let le_u24 = |at: usize| -> Option<u32> {
+        let b = bytes.get(at..at + 3)?;
+        Some(u32::from(b[0]) | u32::from(b[1]) << 8 | u32::from(b[2]) << 16)
+    };
+    match bytes.get(12..16)? {
+        b"VP8X" => Some((le_u24(24)? + 1, le_u24(27)? + 1)),
+        b"VP8 " => Some((
+            u32::from(le_u16(bytes, 26)? & 0x3fff),
+            u32::from(le_u16(bytes, 28)? & 0x3fff),
+        )),
+        b"VP8L" => {
+            let bits = le_u32(bytes, 21)?;
+            Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav(sample_rate: u32, channels: u16, samples: u32) -> Vec<u8> {
+        let data_len = samples * u32::from(channels) * 2;
+        let mut out = Vec::new();
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(36 + data_len).to_le_bytes());
+        out.extend_from_slice(b"WAVEfmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&channels.to_le_bytes());
+        out.extend_from_slice(&sample_rate.to_le_bytes());
+        out.extend_from_slice(&(sample_rate * u32::from(channels) * 2).to_le_bytes());
+        out.extend_from_slice(&(channels * 2).to_le_bytes());
+        out.extend_from_slice(&16u16.to_le_bytes());
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&data_len.to_le_bytes());
+        out.resize(out.len() + data_len as usize, 0);
+        out
+    }
+
+    #[test]
+    fn sniffs_known_formats() {
+        let cases: [(&[u8], Option<&str>); 12] = [
+            (b"\x89PNG\r\n\x1a\n....", Some(IMAGE_PNG)),
+            (b"\xff\xd8\xff\xe0", Some(IMAGE_JPEG)),
+            (b"RIFF\0\0\0\0WAVEfmt ", Some(AUDIO_WAV)),
+            (b"RIFF\0\0\0\0WEBPVP8 ", Some(IMAGE_WEBP)),
+            (b"fLaC\0\0\0\x22", Some(AUDIO_FLAC)),
+            (b"OggS\0\x02", Some(AUDIO_OGG)),
+            (b"ID3\x04\0", Some(AUDIO_MPEG)),
+            (b"\xff\xfb\x90\x64", Some(AUDIO_MPEG)),
+            (b"\xff\xf1\x50\x80", Some(AUDIO_AAC)),
+            (b"\0\0\0\x20ftypM4A \0\0\0\0", Some(AUDIO_MP4)),
+            (b"\0\0\0\x18ftypheic\0\0\0\0", Some(IMAGE_HEIC)),
+            (b"plain text", None),
+        ];
+        for (bytes, mime) in cases {
+            assert_eq!(sniff_mime(bytes), mime, "{bytes:?}");
+        }
+
+        let mut opus = b"OggS".to_vec();
+        opus.resize(28, 0);
+        opus.extend_from_slice(b"OpusHead");
+        assert_eq!(sniff_mime(&opus), Some(AUDIO_OPUS));
+    }
+
+    #[test]
+    fn probes_wav_format_and_duration() {
+        let info = MediaInfo::probe(&wav(16_000, 1, 8_000));
+        assert_eq!(info.mime, Some(AUDIO_WAV));
+        assert_eq!(info.kind(), Some(MediaKind::Audio));
+        assert_eq!(info.sample_rate, Some(16_000));
+        assert_eq!(info.channels, Some(1));
+        assert_eq!(info.duration, Some(Duration::from_millis(500)));
+        assert_eq!(info.width, None);
+
+        let mut empty = wav(16_000, 1, 0);
+        empty.extend_from_slice(b"LIST\x04\x00\x00\x00INFO");
+        assert_eq!(MediaInfo::probe(&empty).duration, Some(Duration::ZERO));
+
+        let mut junk = b"RIFF\0\0\0\0WAVEjunk".to_vec();
+        junk.extend_from_slice(&u32::MAX.to_le_bytes());
+        junk.extend_from_slice(&[0; 16]);
+        let info = MediaInfo::probe(&junk);
+        assert_eq!((info.mime, info.duration), (Some(AUDIO_WAV), None));
+    }
+
+    #[test]
+    fn probes_image_dimensions() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&640u32.to_be_bytes());
+        png.extend_from_slice(&480u32.to_be_bytes());
+        let info = MediaInfo::probe(&png);
+        assert_eq!((info.width, info.height), (Some(640), Some(480)));
+        assert_eq!(info.kind(), Some(MediaKind::Image));
+
+        // SOI, an APP0 segment, then SOF0 with a 1920x1080 frame.
+        let jpeg = [
+            0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, 0x00, 0x00, 0xff, 0xc0, 0x00, 0x11, 0x08, 0x04,
+            0x38, 0x07, 0x80,
+        ];
+        let info = MediaInfo::probe(&jpeg);
+        assert_eq!((info.width, info.height), (Some(1920), Some(1080)));
+    }
+
+    #[test]
+    fn probes_mp4_duration_from_mvhd() {
+        let mut mvhd = vec![0u8; 20];
+        mvhd[12..16].copy_from_slice(&1_000u32.to_be_bytes());
+        mvhd[16..20].copy_from_slice(&2_500u32.to_be_bytes());
+        let mut bytes = b"\0\0\0\x10ftypM4A \0\0\0\0".to_vec();
+        bytes.extend_from_slice(&(16 + mvhd.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(b"moov");
+        bytes.extend_from_slice(&(8 + mvhd.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(b"mvhd");
+        bytes.extend_from_slice(&mvhd);
+
+        let info = MediaInfo::probe(&bytes);
+        assert_eq!(info.mime, Some(AUDIO_MP4));
+        assert_eq!(info.duration, Some(Duration::from_millis(2_500)));
+    }
+
+    #[test]
+    fn oversized_mp4_box_ends_the_walk() {
+        let mut bytes = b"\0\0\0\x10ftypM4A \0\0\0\0".to_vec();
+        bytes.extend_from_slice(b"\0\0\0\x01free");
+        bytes.extend_from_slice(&u64::MAX.to_be_bytes());
+        bytes.extend_from_slice(b"\0\0\0\x08moov");
+
+        assert_eq!(find_box(&bytes, b"moov"), None);
+        let info = MediaInfo::probe(&bytes);
+        assert_eq!(info.mime, Some(AUDIO_MP4));
+        assert_eq!(info.duration, None);
+    }
+}
@@ -0,0 +1,130 @@
+use super::{Blob, BlobId, BlobRef, BlobStore, HABlobError, Index};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+#[derive(Debug, Default)]
+struct Inner {
+    index: Index,
+    data: HashMap<BlobId, Arc<[u8]>>,
+}
+
+// Keeps blobs in memory; `get` hands out the stored `Arc` without copying.
+#[derive(Debug, Default)]
+pub struct MemoryBlobStore {
+    inner: Mutex<Inner>,
+}
+
+impl MemoryBlobStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl BlobStore for MemoryBlobStore {
+    fn put(&self, blob: Blob) -> Result<BlobRef, HABlobError> {
+        let mut inner = self.lock();
+        let mut id = BlobId::of(&blob.bytes);
+        loop {
+            match inner.data.get(&id) {
+                Some(stored) if *stored == blob.bytes => break,
+                Some(_) => id = id.next(),
+                None => {
+                    inner.data.insert(id, blob.bytes.clone());
+                    break;
+                }
+            }
+        }
+        Ok(inner.index.insert(id, blob.len(), blob.mime))
+    }
+
+    fn get(&self, blob_ref: BlobRef) -> Result<Blob, HABlobError> {
+        let inner = self.lock();
+        let handle = inner.index.handle(blob_ref)?;
+        let bytes = inner
+            .data
+            .get(&handle.id)
+            .cloned()
+            .ok_or(HABlobError::NotFound(blob_ref))?;
+        Ok(Blob {
+            bytes,
+            mime: handle.mime,
+        })
+    }
+
+    fn remove(&self, blob_ref: BlobRef) -> Result<(), HABlobError> {
+        let mut inner = self.lock();
+        if let Some(id) = inner.index.release(blob_ref)? {
+            inner.data.remove(&id);
+        }
+        Ok(())
+    }
+
+    fn contains(&self, blob_ref: BlobRef) -> bool {
+        self.lock().index.refs.contains_key(&blob_ref)
+    }
+
+    fn len(&self) -> usize {
+        self.lock().data.len()
+    }
+
+    fn size(&self) -> u64 {
+        self.lock().index.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn put_get_roundtrip_shares_bytes() {
+        let store = MemoryBlobStore::new();
+        let blob = Blob::new(vec![1, 2, 3]).with_mime("audio/wav");
+        let blob_ref = store.put(blob.clone()).unwrap();
+
+        let got = store.get(blob_ref).unwrap();
+        assert_eq!(got, blob);
+        assert!(Arc::ptr_eq(&got.bytes, &blob.bytes));
+        assert!(store.contains(blob_ref));
+    }
+
+    #[test]
+    fn duplicates_are_stored_once_and_refcounted() {
+        let store = MemoryBlobStore::new();
+        let first = store.put(Blob::new(vec![7; 10])).unwrap();
+        let second = store.put(Blob::new(vec![7; 10])).unwrap();
+        let other = store.put(Blob::new(vec![8; 4])).unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.size(), 14);
+
+        store.remove(first).unwrap();
+        assert_eq!(store.size(), 14);
+        assert_eq!(store.get(second).unwrap().len(), 10);
+
+        store.remove(second).unwrap();
+        store.remove(other).unwrap();
+        assert!(store.is_empty());
+        assert_eq!(store.size(), 0);
+    }
+
+    #[test]
+    fn unknown_ref_is_not_found() {
+        let store = MemoryBlobStore::new();
+        let blob_ref = store.put(Blob::new(vec![1])).unwrap();
+        store.remove(blob_ref).unwrap();
+
+        assert!(matches!(store.get(blob_ref), Err(HABlobError::NotFound(_))));
+        assert!(matches!(
+            store.remove(blob_ref),
+            Err(HABlobError::NotFound(_))
+        ));
+    }
+}
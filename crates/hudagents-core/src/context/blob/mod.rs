@@ -0,0 +1,224 @@
+// Content-addressed storage for audio and image payloads. Messages carry a `BlobRef` handle;
+// the store keeps one copy per distinct content (`BlobId`) and counts the handles pointing at it.
+pub mod file;
+pub mod manager;
+pub mod media;
+pub mod memory;
+
+pub use file::FileBlobStore;
+pub use manager::BlobManager;
+pub use media::{MediaInfo, MediaKind, sniff_mime};
+pub use memory::MemoryBlobStore;
+
+use super::ids::UserId;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display},
+    io,
+    sync::Arc,
+};
+
+// Used to referebce Blob Object inside the Message Payloa
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct BlobRef(pub u64);
+
+// Used to store Blob objects inside the internal storage
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct BlobId(pub u64);
+
+impl BlobId {
+    // FNV-1a over the content. Collisions are resolved by the stores, which compare bytes
+    // and probe the next id.
+    pub fn of(bytes: &[u8]) -> Self {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for &byte in bytes {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        BlobId(hash)
+    }
+
+    fn next(self) -> Self {
+        BlobId(self.0.wrapping_add(1))
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Blob {
+    pub bytes: Arc<[u8]>,
+    pub mime: Option<&'static str>,
+}
+
+impl Blob {
+    // The MIME type is sniffed from the magic bytes; use `with_mime` to override it.
+    pub fn new(bytes: impl Into<Arc<[u8]>>) -> Self {
+        let bytes = bytes.into();
+        Self {
+            mime: sniff_mime(&bytes),
+            bytes,
+        }
+    }
+
+    pub fn with_mime(mut self, mime: &'static str) -> Self {
+        self.mime = Some(mime);
+        self
+    }
+
+    pub fn kind(&self) -> Option<MediaKind> {
+        self.mime.and_then(MediaKind::of)
+    }
+
+    // Parses the headers; see `MediaInfo` for what is available per format.
+    pub fn info(&self) -> MediaInfo {
+        MediaInfo {
+            mime: self.mime,
+            ..MediaInfo::probe(&self.bytes)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl From<Vec<u8>> for Blob {
+    fn from(bytes: Vec<u8>) -> Self {
+        Blob::new(bytes)
+    }
+}
+
+impl AsRef<[u8]> for Blob {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+#[derive(Debug)]
+pub enum HABlobError {
+    NotFound(BlobRef),
+    QuotaExceeded {
+        user: UserId,
+        bytes: u64,
+        quota: u64,
+    },
+    Io(io::Error),
+}
+
+impl Display for HABlobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HABlobError::NotFound(blob_ref) => write!(f, "blob not found: {}", blob_ref.0),
+            HABlobError::QuotaExceeded { user, bytes, quota } => write!(
+                f,
+                "blob of {} bytes exceeds the {} byte quota of user {}",
+                bytes, quota, user.0
+            ),
+            HABlobError::Io(e) => write!(f, "blob storage failed: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for HABlobError {
+    fn from(e: io::Error) -> Self {
+        HABlobError::Io(e)
+    }
+}
+
+impl Error for HABlobError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HABlobError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// Every `put` hands out a new `BlobRef`, even for content that is already stored; `remove`
+// releases one handle and the bytes go away with the last one. Sizes count stored bytes once.
+pub trait BlobStore {
+    fn put(&self, blob: Blob) -> Result<BlobRef, HABlobError>;
+    fn get(&self, blob_ref: BlobRef) -> Result<Blob, HABlobError>;
+    fn remove(&self, blob_ref: BlobRef) -> Result<(), HABlobError>;
+    fn contains(&self, blob_ref: BlobRef) -> bool;
+    // Number of distinct contents stored.
+    fn len(&self) -> usize;
+    // Total stored bytes, duplicates counted once.
+    fn size(&self) -> u64;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Handle {
+    id: BlobId,
+    mime: Option<&'static str>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Stored {
+    len: u64,
+    refs: usize,
+}
+
+// Handle and refcount bookkeeping shared by the backends.
+#[derive(Debug, Default)]
+struct Index {
+    refs: HashMap<BlobRef, Handle>,
+    blobs: HashMap<BlobId, Stored>,
+    next_ref: u64,
+    bytes: u64,
+}
+
+impl Index {
+    fn insert(&mut self, id: BlobId, len: usize, mime: Option<&'static str>) -> BlobRef {
+        let blob_ref = BlobRef(self.next_ref);
+        self.restore(blob_ref, id, len as u64, mime);
+        blob_ref
+    }
+
+    // Adds a handle with a given ref, e.g. one issued before a restart. Refs below `next_ref`
+    // are never handed out again.
+    fn restore(&mut self, blob_ref: BlobRef, id: BlobId, len: u64, mime: Option<&'static str>) {
+        let stored = self.blobs.entry(id).or_insert(Stored { len, refs: 0 });
+        if stored.refs == 0 {
+            self.bytes += stored.len;
+        }
+        stored.refs += 1;
+
+        self.next_ref = self.next_ref.max(blob_ref.0 + 1);
+        self.refs.insert(blob_ref, Handle { id, mime });
+    }
+
+    fn handle(&self, blob_ref: BlobRef) -> Result<Handle, HABlobError> {
+        self.refs
+            .get(&blob_ref)
+            .copied()
+            .ok_or(HABlobError::NotFound(blob_ref))
+    }
+
+    // Drops a handle. Returns the content id when it was the last handle to it.
+    fn release(&mut self, blob_ref: BlobRef) -> Result<Option<BlobId>, HABlobError> {
+        let handle = self
+            .refs
+            .remove(&blob_ref)
+            .ok_or(HABlobError::NotFound(blob_ref))?;
+        let Some(stored) = self.blobs.get_mut(&handle.id) else {
+            return Ok(None);
+        };
+        stored.refs -= 1;
+        if stored.refs > 0 {
+            return Ok(None);
+        }
+        self.bytes -= stored.len;
+        self.blobs.remove(&handle.id);
+        Ok(Some(handle.id))
+    }
+}
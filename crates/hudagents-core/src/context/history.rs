@@ -0,0 +1,173 @@
+// Filters over the message history of an `AgentContext`; every filter that is set must match.
+use super::{
+    AgentContext,
+    ids::RunId,
+    message::{AgentMessage, PayloadKind, Sender},
+};
+use std::time::SystemTime;
+
+#[derive(Clone)]
+pub struct HistoryQuery<'a> {
+    context: &'a AgentContext,
+    run: Option<RunId>,
+    from: Option<Sender>,
+    since: Option<SystemTime>,
+    after_seq: Option<u64>,
+    // Any of these kinds; empty matches all.
+    kinds: Vec<PayloadKind>,
+}
+
+impl<'a> HistoryQuery<'a> {
+    pub fn new(context: &'a AgentContext) -> Self {
+        Self {
+            context,
+            run: None,
+            from: None,
+            since: None,
+            after_seq: None,
+            kinds: Vec::new(),
+        }
+    }
+
+    pub fn run(mut self, run: RunId) -> Self {
+        self.run = Some(run);
+        self
+    }
+
+    pub fn current_run(self) -> Self {
+        let run = self.context.run_id.clone();
+        self.run(run)
+    }
+
+    pub fn from(mut self, sender: Sender) -> Self {
+        self.from = Some(sender);
+        self
+    }
+
+    // Messages created at or after `time`.
+    pub fn since(mut self, time: SystemTime) -> Self {
+        self.since = Some(time);
+        self
+    }
+
+    pub fn after_seq(mut self, seq: u64) -> Self {
+        self.after_seq = Some(seq);
+        self
+    }
+
+    pub fn kind(mut self, kind: PayloadKind) -> Self {
+        self.kinds.push(kind);
+        self
+    }
+
+    pub fn matches(&self, msg: &AgentMessage) -> bool {
+        self.run.as_ref().is_none_or(|run| msg.run == *run)
+            && self.from.as_ref().is_none_or(|from| msg.from == *from)
+            && self.since.is_none_or(|since| msg.timestamp >= since)
+            && self.after_seq.is_none_or(|seq| msg.seq > seq)
+            && (self.kinds.is_empty() || self.kinds.contains(&msg.payload.kind()))
+    }
+
+    // Matching messages, oldest first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &'a AgentMessage> + '_ {
+        self.context.msg_que.iter().filter(|msg| self.matches(msg))
+    }
+
+    pub fn last(&self) -> Option<&'a AgentMessage> {
+        self.iter().next_back()
+    }
+
+    pub fn count(&self) -> usize {
+        self.iter().count()
+    }
+
+    // Text of the matching messages that carry text, oldest first.
+    pub fn texts(&self) -> Vec<&'a str> {
+        self.iter().filter_map(AgentMessage::text).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::{ids::UserId, message::MessagePayload},
+        graph::NodeId,
+    };
+    use std::time::Duration;
+
+    fn context() -> AgentContext {
+        let mut cx = AgentContext::new(RunId(1), UserId(1), 16);
+        let messages = [
+            (
+                1,
+                Sender::Node(NodeId(1)),
+                MessagePayload::Transcription("hello".into()),
+            ),
+            (
+                1,
+                Sender::Node(NodeId(2)),
+                MessagePayload::VisionCaption("a door".into()),
+            ),
+            (
+                2,
+                Sender::Node(NodeId(1)),
+                MessagePayload::Transcription("what is it".into()),
+            ),
+            (
+                2,
+                Sender::Node(NodeId(2)),
+                MessagePayload::VisionCaption("a mug".into()),
+            ),
+            (
+                2,
+                Sender::Node(NodeId(3)),
+                MessagePayload::FinalAnswer("a blue mug".into()),
+            ),
+        ];
+        for (run, from, payload) in messages {
+            cx.run_id = RunId(run);
+            cx.push(AgentMessage::new(RunId(run), from, payload));
+        }
+        cx
+    }
+
+    #[test]
+    fn shortcuts_cover_common_prompts() {
+        let cx = context();
+        assert_eq!(cx.last_transcription(), Some("what is it"));
+        assert_eq!(cx.vision_captions(), ["a mug"]);
+    }
+
+    #[test]
+    fn filters_combine() {
+        let cx = context();
+        assert_eq!(
+            cx.query().from(Sender::Node(NodeId(1))).texts(),
+            ["hello", "what is it"]
+        );
+        assert_eq!(cx.query().run(RunId(1)).count(), 2);
+        assert_eq!(
+            cx.query()
+                .kind(PayloadKind::VisionCaption)
+                .kind(PayloadKind::FinalAnswer)
+                .after_seq(1)
+                .texts(),
+            ["a mug", "a blue mug"]
+        );
+        assert_eq!(cx.query().last().map(|msg| msg.seq), Some(4));
+    }
+
+    #[test]
+    fn since_uses_message_timestamps() {
+        let mut cx = context();
+        let cutoff = SystemTime::now() + Duration::from_secs(60);
+        assert_eq!(cx.query().since(cutoff).count(), 0);
+
+        let mut late =
+            AgentMessage::new(RunId(2), Sender::User, MessagePayload::Text("later".into()));
+        late.timestamp = cutoff + Duration::from_secs(1);
+        cx.push(late);
+        assert_eq!(cx.query().since(cutoff).texts(), ["later"]);
+    }
+}
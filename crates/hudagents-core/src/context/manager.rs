@@ -0,0 +1,434 @@
+// Tracks the live conversation of every (user, device) pair served by one backend.
+use super::{
+    AgentContext,
+    ids::{DeviceId, RunId, UserId},
+    session::{HASessionError, SessionStore},
+};
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+pub type SessionKey = (UserId, DeviceId);
+pub type SharedContext = Arc<Mutex<AgentContext>>;
+// Configures freshly created or resumed contexts (token budget, summarizer, blobs, ...).
+pub type ContextSetup = Arc<dyn Fn(AgentContext) -> AgentContext + Send + Sync>;
+
+struct Session {
+    context: SharedContext,
+    last_active: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct ExpiredSessions {
+    pub closed: Vec<SessionKey>,
+    // Sessions that could not be saved; they are still open.
+    pub failed: Vec<(SessionKey, HASessionError)>,
+}
+
+impl ExpiredSessions {
+    pub fn is_empty(&self) -> bool {
+        self.closed.is_empty() && self.failed.is_empty()
+    }
+}
+
+// Safe to share across threads, e.g. in an `Arc`. With a session store, contexts are resumed
+// from the user's latest snapshot when a device connects and saved when a session is closed or
+// expires.
+pub struct SessionManager {
+    sessions: Mutex<HashMap<SessionKey, Session>>,
+    next_run: AtomicU64,
+    capacity: usize,
+    idle_timeout: Option<Duration>,
+    setup: Option<ContextSetup>,
+    store: Option<Arc<dyn SessionStore + Send + Sync>>,
+}
+
+impl SessionManager {
+    // `capacity` is the message capacity of new contexts.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            next_run: AtomicU64::new(1),
+            capacity,
+            idle_timeout: None,
+            setup: None,
+            store: None,
+        }
+    }
+
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_context_setup(
+        mut self,
+        setup: impl Fn(AgentContext) -> AgentContext + Send + Sync + 'static,
+    ) -> Self {
+        self.setup = Some(Arc::new(setup));
+        self
+    }
+
+    pub fn with_store(mut self, store: Arc<dyn SessionStore + Send + Sync>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<SessionKey, Session>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Run ids are unique and increasing across all sessions of this manager.
+    pub fn next_run_id(&self) -> RunId {
+        RunId(self.next_run.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    pub fn keys(&self) -> Vec<SessionKey> {
+        self.lock().keys().cloned().collect()
+    }
+
+    // Returns the context of the pair, resuming or creating it when there is none, and marks
+    // the session as active. A device joining a user whose other device is already live resumes
+    // the same conversation under a fresh run id, so their saves do not overwrite each other.
+    pub fn session(
+        &self,
+        user_id: &UserId,
+        device_id: &DeviceId,
+    ) -> Result<SharedContext, HASessionError> {
+        let key = (user_id.clone(), device_id.clone());
+        if let Some(session) = self.lock().get_mut(&key) {
+            session.last_active = Instant::now();
+            return Ok(session.context.clone());
+        }
+
+        // Loaded without holding the lock, so a slow store does not stall every other device.
+        let (context, resumed) = match self.resume(user_id)? {
+            Some(context) => (context, true),
+            None => (
+                AgentContext::new(self.next_run_id(), user_id.clone(), self.capacity),
+                false,
+            ),
+        };
+        let mut context = match &self.setup {
+            Some(setup) => setup(context),
+            None => context,
+        };
+
+        let mut sessions = self.lock();
+        let shared = sessions
+            .keys()
+            .any(|(user, device)| user == user_id && device != device_id);
+        match sessions.entry(key) {
+            // Another thread connected the pair in the meantime.
+            Entry::Occupied(mut entry) => {
+                entry.get_mut().last_active = Instant::now();
+                Ok(entry.get().context.clone())
+            }
+            Entry::Vacant(entry) => {
+                if resumed && shared {
+                    context.run_id = self.next_run_id();
+                }
+                let context = Arc::new(Mutex::new(context));
+                entry.insert(Session {
+                    context: context.clone(),
+                    last_active: Instant::now(),
+                });
+                Ok(context)
+            }
+        }
+    }
+
+    fn resume(&self, user_id: &UserId) -> Result<Option<AgentContext>, HASessionError> {
+        let Some(store) = &self.store else {
+            return Ok(None);
+        };
+        let resumed = AgentContext::resume(store.as_ref(), user_id)?;
+        if let Some(context) = &resumed {
+            // Never hand out a run id the resumed conversation already used.
+            self.next_run
+                .fetch_max(context.run_id.0 + 1, Ordering::Relaxed);
+        }
+        Ok(resumed)
+    }
+
+    // Starts a new run in the pair's session and returns its id.
+    pub fn start_run(
+        &self,
+        user_id: &UserId,
+        device_id: &DeviceId,
+    ) -> Result<RunId, HASessionError> {
+        let context = self.session(user_id, device_id)?;
+        let run_id = self.next_run_id();
+        context.lock().unwrap_or_else(|e| e.into_inner()).run_id = run_id.clone();
+        Ok(run_id)
+    }
+
+    // Removes the session, saving it first when a store is configured. A session whose save
+    // fails stays open, so the conversation is not lost.
+    pub fn close(&self, user_id: &UserId, device_id: &DeviceId) -> Result<bool, HASessionError> {
+        let key = (user_id.clone(), device_id.clone());
+        let Some(session) = self.lock().remove(&key) else {
+            return Ok(false);
+        };
+        if let Err(e) = self.save(&session) {
+            self.lock().entry(key).or_insert(session);
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    // Closes every session idle for longer than the timeout. Every expired session is saved;
+    // those whose save fails stay open and are reported with their error.
+    pub fn expire_idle(&self) -> ExpiredSessions {
+        let mut report = ExpiredSessions::default();
+        let Some(timeout) = self.idle_timeout else {
+            return report;
+        };
+        let expired: Vec<(SessionKey, Session)> = {
+            let mut sessions = self.lock();
+            let keys: Vec<SessionKey> = sessions
+                .iter()
+                .filter(|(_, session)| session.last_active.elapsed() > timeout)
+                .map(|(key, _)| key.clone())
+                .collect();
+            keys.into_iter()
+                .filter_map(|key| sessions.remove(&key).map(|session| (key, session)))
+                .collect()
+        };
+        let mut kept = Vec::new();
+        for (key, session) in expired {
+            match self.save(&session) {
+                Ok(()) => report.closed.push(key),
+                Err(e) => {
+                    report.failed.push((key.clone(), e));
+                    kept.push((key, session));
+                }
+            }
+        }
+        if !kept.is_empty() {
+            let mut sessions = self.lock();
+            for (key, session) in kept {
+                sessions.entry(key).or_insert(session);
+            }
+        }
+        report
+    }
+
+    fn save(&self, session: &Session) -> Result<(), HASessionError> {
+        if let Some(store) = &self.store {
+            let snapshot = session
+                .context
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .snapshot();
+            store.save(&snapshot)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::{
+        message::{AgentMessage, MessagePayload, Sender},
+        session::{ContextSnapshot, FileSessionStore, RetentionPolicy},
+    };
+    use std::{collections::HashSet, fs, io, thread};
+
+    // Refuses to save the snapshots of one user.
+    struct FlakyStore {
+        broken: UserId,
+        saved: Mutex<Vec<RunId>>,
+    }
+
+    impl SessionStore for FlakyStore {
+        fn save(&self, snapshot: &ContextSnapshot) -> Result<(), HASessionError> {
+            if snapshot.user_id == self.broken {
+                return Err(io::Error::other("disk full").into());
+            }
+            self.saved.lock().unwrap().push(snapshot.run_id.clone());
+            Ok(())
+        }
+
+        fn load(
+            &self,
+            _user_id: &UserId,
+            _run_id: &RunId,
+        ) -> Result<Option<ContextSnapshot>, HASessionError> {
+            Ok(None)
+        }
+
+        fn load_latest(
+            &self,
+            _user_id: &UserId,
+        ) -> Result<Option<ContextSnapshot>, HASessionError> {
+            Ok(None)
+        }
+
+        fn remove(&self, _user_id: &UserId, _run_id: &RunId) -> Result<(), HASessionError> {
+            Ok(())
+        }
+
+        fn prune(&self, _policy: &RetentionPolicy) -> Result<usize, HASessionError> {
+            Ok(0)
+        }
+    }
+
+    fn pair(user: usize, device: &str) -> (UserId, DeviceId) {
+        (UserId(user), DeviceId(device.into()))
+    }
+
+    #[test]
+    fn sessions_are_keyed_by_user_and_device() {
+        let manager = SessionManager::new(8).with_context_setup(|cx| cx.with_token_budget(64));
+        let (alice, left) = pair(1, "left");
+        let right = DeviceId("right".into());
+
+        let first = manager.session(&alice, &left).unwrap();
+        let again = manager.session(&alice, &left).unwrap();
+        let other = manager.session(&alice, &right).unwrap();
+
+        assert!(Arc::ptr_eq(&first, &again));
+        assert!(!Arc::ptr_eq(&first, &other));
+        assert_eq!(manager.len(), 2);
+        assert_eq!(first.lock().unwrap().token_budget, Some(64));
+        assert_ne!(first.lock().unwrap().run_id, other.lock().unwrap().run_id);
+    }
+
+    #[test]
+    fn run_ids_are_unique_across_threads() {
+        let manager = Arc::new(SessionManager::new(8));
+        let handles: Vec<_> = (0..4)
+            .map(|user| {
+                let manager = manager.clone();
+                thread::spawn(move || {
+                    let (user, device) = pair(user, "glasses");
+                    (0..25)
+                        .map(|_| manager.start_run(&user, &device).unwrap().0)
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        let mut seen = HashSet::new();
+        for handle in handles {
+            let ids = handle.join().unwrap();
+            assert!(ids.windows(2).all(|w| w[0] < w[1]));
+            seen.extend(ids);
+        }
+        assert_eq!(seen.len(), 100);
+    }
+
+    #[test]
+    fn idle_sessions_expire_and_resume_from_store() {
+        let root = std::env::temp_dir().join(format!("hudagents-manager-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let store = Arc::new(FileSessionStore::new(&root).unwrap());
+        let manager = SessionManager::new(8)
+            .with_idle_timeout(Duration::from_millis(20))
+            .with_store(store.clone());
+        let (user, device) = pair(3, "glasses");
+
+        let run_id = manager.start_run(&user, &device).unwrap();
+        manager
+            .session(&user, &device)
+            .unwrap()
+            .lock()
+            .unwrap()
+            .push(AgentMessage::new(
+                run_id.clone(),
+                Sender::User,
+                MessagePayload::Text("remember me".into()),
+            ));
+        assert!(manager.expire_idle().is_empty());
+
+        thread::sleep(Duration::from_millis(40));
+        assert_eq!(
+            manager.expire_idle().closed,
+            vec![(user.clone(), device.clone())]
+        );
+        assert!(manager.is_empty());
+
+        let resumed = SessionManager::new(8).with_store(store);
+        let context = resumed.session(&user, &device).unwrap();
+        assert_eq!(context.lock().unwrap().run_id, run_id);
+        assert_eq!(context.lock().unwrap().len(), 1);
+        assert!(resumed.next_run_id().0 > run_id.0);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn second_device_resumes_under_a_fresh_run_id() {
+        let root =
+            std::env::temp_dir().join(format!("hudagents-manager-devices-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let store = Arc::new(FileSessionStore::new(&root).unwrap());
+        let user = UserId(4);
+        let mut cx = AgentContext::new(RunId(9), user.clone(), 8);
+        cx.push(AgentMessage::new(
+            RunId(9),
+            Sender::User,
+            MessagePayload::Text("earlier".into()),
+        ));
+        store.save(&cx.snapshot()).unwrap();
+
+        let manager = SessionManager::new(8).with_store(store);
+        let left = manager.session(&user, &DeviceId("left".into())).unwrap();
+        let right = manager.session(&user, &DeviceId("right".into())).unwrap();
+        let (left, right) = (left.lock().unwrap(), right.lock().unwrap());
+        assert_eq!(left.run_id, RunId(9));
+        assert!(right.run_id.0 > 9);
+        assert_eq!(right.len(), 1);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn failed_saves_keep_their_sessions_open() {
+        let store = Arc::new(FlakyStore {
+            broken: UserId(1),
+            saved: Mutex::new(Vec::new()),
+        });
+        let manager = SessionManager::new(8)
+            .with_idle_timeout(Duration::ZERO)
+            .with_store(store.clone());
+        let (broken, device) = pair(1, "glasses");
+        let fine = UserId(2);
+        manager.session(&broken, &device).unwrap();
+        let run_id = manager
+            .session(&fine, &device)
+            .unwrap()
+            .lock()
+            .unwrap()
+            .run_id
+            .clone();
+        thread::sleep(Duration::from_millis(5));
+
+        let expired = manager.expire_idle();
+        assert_eq!(expired.closed, vec![(fine, device.clone())]);
+        assert!(matches!(
+            expired.failed.as_slice(),
+            [(key, HASessionError::Io(_))] if *key == (broken.clone(), device.clone())
+        ));
+        assert_eq!(*store.saved.lock().unwrap(), vec![run_id]);
+        assert_eq!(manager.keys(), vec![(broken.clone(), device.clone())]);
+
+        assert!(manager.close(&broken, &device).is_err());
+        assert_eq!(manager.len(), 1);
+    }
+}
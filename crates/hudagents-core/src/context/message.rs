@@ -1,15 +1,22 @@
 use super::Control;
-use super::blob::BlobRef;
+use super::blob::{BlobRef, BlobStore, HABlobError};
 use super::ids::RunId;
-use crate::graph::NodeId;
+use crate::{
+    agent::{AgentInput, AgentOutput},
+    graph::NodeId,
+};
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Sender {
     User,
     Node(NodeId),
+    // Written by the context itself, e.g. summaries of compacted messages.
+    System,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum MessagePayload {
     Text(String),
     Audio(BlobRef),
@@ -21,9 +28,142 @@ pub enum MessagePayload {
     Error(String),
 }
 
-#[derive(Clone, Debug)]
+impl From<AgentOutput> for MessagePayload {
+    fn from(output: AgentOutput) -> Self {
+        match output {
+            // Segment timing is not kept in the history, only the text.
+            AgentOutput::AudioTranscription(transcript) => {
+                MessagePayload::Transcription(transcript.text)
+            }
+            AgentOutput::ImageInterpretation(text) => MessagePayload::VisionCaption(text),
+            AgentOutput::FinalAnswer(text) => MessagePayload::FinalAnswer(text),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum PayloadKind {
+    Text,
+    Audio,
+    Image,
+    Transcription,
+    VisionCaption,
+    FinalAnswer,
+    Control,
+    Error,
+}
+
+impl MessagePayload {
+    pub fn kind(&self) -> PayloadKind {
+        match self {
+            MessagePayload::Text(_) => PayloadKind::Text,
+            MessagePayload::Audio(_) => PayloadKind::Audio,
+            MessagePayload::Image(_) => PayloadKind::Image,
+            MessagePayload::Transcription(_) => PayloadKind::Transcription,
+            MessagePayload::VisionCaption(_) => PayloadKind::VisionCaption,
+            MessagePayload::FinalAnswer(_) => PayloadKind::FinalAnswer,
+            MessagePayload::Control(_) => PayloadKind::Control,
+            MessagePayload::Error(_) => PayloadKind::Error,
+        }
+    }
+
+    pub fn blob_ref(&self) -> Option<BlobRef> {
+        match self {
+            MessagePayload::Audio(blob_ref) | MessagePayload::Image(blob_ref) => Some(*blob_ref),
+            _ => None,
+        }
+    }
+
+    // Turns a payload into agent input, loading audio and image bytes from the store.
+    // Control and error messages are not agent inputs and give `None`.
+    pub fn to_input(&self, store: &dyn BlobStore) -> Result<Option<AgentInput>, HABlobError> {
+        let input = match self {
+            MessagePayload::Audio(blob_ref) => AgentInput::Audio(store.get(*blob_ref)?),
+            MessagePayload::Image(blob_ref) => AgentInput::Image(store.get(*blob_ref)?),
+            MessagePayload::Text(text)
+            | MessagePayload::Transcription(text)
+            | MessagePayload::VisionCaption(text)
+            | MessagePayload::FinalAnswer(text) => AgentInput::Text(text.clone()),
+            MessagePayload::Control(_) | MessagePayload::Error(_) => return Ok(None),
+        };
+        Ok(Some(input))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AgentMessage {
     pub run: RunId,
     pub from: Sender,
     pub payload: MessagePayload,
+    // Pinned messages are never evicted or summarized away.
+    pub pinned: bool,
+    // Position in the context, assigned by `AgentContext::push`; increases with every push.
+    pub seq: u64,
+    pub timestamp: SystemTime,
+}
+
+impl AgentMessage {
+    pub fn new(run: RunId, from: Sender, payload: MessagePayload) -> Self {
+        Self {
+            run,
+            from,
+            payload,
+            pinned: false,
+            seq: 0,
+            timestamp: SystemTime::now(),
+        }
+    }
+
+    pub fn pin(mut self) -> Self {
+        self.pinned = true;
+        self
+    }
+
+    // Text carried by the message, if any.
+    pub fn text(&self) -> Option<&str> {
+        match &self.payload {
+            MessagePayload::Text(text)
+            | MessagePayload::Transcription(text)
+            | MessagePayload::VisionCaption(text)
+            | MessagePayload::FinalAnswer(text)
+            | MessagePayload::Error(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::blob::{Blob, MemoryBlobStore};
+
+    #[test]
+    fn to_input_resolves_blob_payloads() {
+        let store = MemoryBlobStore::new();
+        let blob_ref = store
+            .put(Blob::new(vec![1, 2]).with_mime("audio/wav"))
+            .unwrap();
+
+        match MessagePayload::Audio(blob_ref).to_input(&store).unwrap() {
+            Some(AgentInput::Audio(blob)) => {
+                assert_eq!(&blob.bytes[..], &[1, 2]);
+                assert_eq!(blob.mime, Some("audio/wav"));
+            }
+            other => panic!("unexpected input: {other:?}"),
+        }
+        assert!(matches!(
+            MessagePayload::Transcription("hi".into()).to_input(&store),
+            Ok(Some(AgentInput::Text(text))) if text == "hi"
+        ));
+        assert!(matches!(
+            MessagePayload::Control(Control::Continue).to_input(&store),
+            Ok(None)
+        ));
+
+        store.remove(blob_ref).unwrap();
+        assert!(matches!(
+            MessagePayload::Image(blob_ref).to_input(&store),
+            Err(HABlobError::NotFound(_))
+        ));
+    }
 }
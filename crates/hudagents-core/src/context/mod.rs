@@ -1,24 +1,56 @@
 pub mod blob;
+pub mod history;
 pub mod ids;
+pub mod manager;
 pub mod message;
+pub mod session;
 
 use super::graph::NodeId;
+use blob::BlobManager;
+use history::HistoryQuery;
 use ids::{RunId, UserId};
-use message::AgentMessage;
-use std::collections::VecDeque;
+use message::{AgentMessage, MessagePayload, PayloadKind, Sender};
+use serde::{Deserialize, Serialize};
+use session::{ContextSnapshot, HASessionError, SessionStore};
+use std::{collections::VecDeque, ops::Range, sync::Arc};
 
-#[derive(Clone, Debug)]
+// Rough token cost of an audio or image blob when estimating the window size.
+pub const BLOB_TOKENS: usize = 256;
+
+// Estimates how many tokens a message takes in an LLM prompt.
+pub type TokenCounter = Arc<dyn Fn(&AgentMessage) -> usize + Send + Sync>;
+
+// Compacts evicted messages into a single summary text.
+pub type Summarizer = Arc<dyn Fn(&[AgentMessage]) -> String + Send + Sync>;
+
+// About four characters per token, plus a little per-message overhead.
+pub fn estimate_tokens(msg: &AgentMessage) -> usize {
+    match &msg.payload {
+        MessagePayload::Audio(_) | MessagePayload::Image(_) => BLOB_TOKENS,
+        _ => msg.text().map_or(0, |text| text.len().div_ceil(4)) + 4,
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Control {
     RetryNode(NodeId),
     SkipNode(NodeId),
     Continue,
 }
 
+#[derive(Clone)]
 pub struct AgentContext {
     pub run_id: RunId,
     pub user_id: UserId,
     pub capacity: usize,
     pub msg_que: VecDeque<AgentMessage>,
+    pub next_seq: u64,
+    // When set, the window is also bounded by the estimated token count.
+    pub token_budget: Option<usize>,
+    pub token_counter: TokenCounter,
+    pub summarizer: Option<Summarizer>,
+    // When set, blobs referenced by evicted messages are released.
+    pub blobs: Option<Arc<BlobManager>>,
 }
 
 impl AgentContext {
@@ -28,9 +60,45 @@ impl AgentContext {
             user_id,
             capacity,
             msg_que: VecDeque::new(),
+            next_seq: 0,
+            token_budget: None,
+            token_counter: Arc::new(estimate_tokens),
+            summarizer: None,
+            blobs: None,
         }
     }
 
+    pub fn with_token_budget(mut self, tokens: usize) -> Self {
+        self.token_budget = Some(tokens);
+        self
+    }
+
+    pub fn with_token_counter(
+        mut self,
+        counter: impl Fn(&AgentMessage) -> usize + Send + Sync + 'static,
+    ) -> Self {
+        self.token_counter = Arc::new(counter);
+        self
+    }
+
+    pub fn with_summarizer(
+        mut self,
+        summarizer: impl Fn(&[AgentMessage]) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.summarizer = Some(Arc::new(summarizer));
+        self
+    }
+
+    // Blobs of pinned messages, those already held and those pushed later, are pinned in the
+    // manager so quota eviction leaves them alone.
+    pub fn with_blobs(mut self, blobs: Arc<BlobManager>) -> Self {
+        for msg in self.msg_que.iter().filter(|msg| msg.pinned) {
+            pin_blob(&blobs, msg);
+        }
+        self.blobs = Some(blobs);
+        self
+    }
+
     pub fn cap(&self) -> usize {
         self.capacity
     }
@@ -43,11 +111,190 @@ impl AgentContext {
         self.msg_que.is_empty()
     }
 
-    pub fn push(&mut self, msg: AgentMessage) {
-        if self.msg_que.len() == self.capacity {
-            self.msg_que.pop_front();
+    // Messages are copied as they are; audio and image payloads keep their `BlobRef`s and only
+    // resolve while the blob store that issued them still holds the blobs. A `FileBlobStore`
+    // keeps its refs across restarts.
+    pub fn snapshot(&self) -> ContextSnapshot {
+        ContextSnapshot {
+            run_id: self.run_id.clone(),
+            user_id: self.user_id.clone(),
+            capacity: self.capacity,
+            token_budget: self.token_budget,
+            messages: self.msg_que.iter().cloned().collect(),
+            saved_at: session::unix_now(),
+        }
+    }
+
+    // Rebuilds a context from a snapshot. The token counter, summarizer and blob manager are not
+    // persisted; attach them again with the `with_*` methods.
+    pub fn restore(snapshot: ContextSnapshot) -> Self {
+        let mut cx = AgentContext::new(snapshot.run_id, snapshot.user_id, snapshot.capacity);
+        cx.token_budget = snapshot.token_budget;
+        cx.next_seq = snapshot.messages.last().map_or(0, |msg| msg.seq + 1);
+        cx.msg_que = snapshot.messages.into();
+        cx
+    }
+
+    // Picks up the user's most recent saved session, if any.
+    pub fn resume(
+        store: &dyn SessionStore,
+        user_id: &UserId,
+    ) -> Result<Option<Self>, HASessionError> {
+        Ok(store.load_latest(user_id)?.map(AgentContext::restore))
+    }
+
+    pub fn tokens(&self) -> usize {
+        self.msg_que
+            .iter()
+            .map(|msg| (self.token_counter)(msg))
+            .sum()
+    }
+
+    pub fn push(&mut self, mut msg: AgentMessage) {
+        msg.seq = self.next_seq;
+        self.next_seq += 1;
+        if msg.pinned
+            && let Some(blobs) = &self.blobs
+        {
+            pin_blob(blobs, &msg);
         }
         self.msg_que.push_back(msg);
+        self.compact();
+    }
+
+    fn over_limit(&self, len: usize, tokens: usize) -> bool {
+        len > self.capacity || self.token_budget.is_some_and(|budget| tokens > budget)
+    }
+
+    // Evicts the oldest unpinned messages until the window fits both the message capacity and
+    // the token budget. The newest message always stays. With a summarizer the evicted messages
+    // are replaced by one summary in their place; a summary only covers a run of unpinned
+    // messages between pinned ones, so the order of the conversation is kept. The summary is
+    // unpinned and gets folded into the next one. When no summary can shrink the window, the
+    // oldest unpinned messages are dropped instead.
+    fn compact(&mut self) {
+        while self.over_limit(self.msg_que.len(), self.window_tokens()) {
+            let mut evicted = match self.summarizer.clone() {
+                Some(summarizer) => self.summarize_pass(&summarizer),
+                None => Vec::new(),
+            };
+            if evicted.is_empty() {
+                evicted = self.drop_pass();
+            }
+            if evicted.is_empty() {
+                break;
+            }
+            if let Some(blobs) = &self.blobs {
+                for blob_ref in evicted.iter().filter_map(|msg| msg.payload.blob_ref()) {
+                    // The blob may already be gone (quota eviction, finished run).
+                    let _ = blobs.release(blob_ref);
+                }
+            }
+        }
+    }
+
+    fn window_tokens(&self) -> usize {
+        match self.token_budget {
+            Some(_) => self.tokens(),
+            None => 0,
+        }
+    }
+
+    fn cost(&self, msg: &AgentMessage) -> usize {
+        match self.token_budget {
+            Some(_) => (self.token_counter)(msg),
+            None => 0,
+        }
+    }
+
+    // Drops the oldest unpinned messages until the window fits.
+    fn drop_pass(&mut self) -> Vec<AgentMessage> {
+        let (mut len, mut tokens) = (self.msg_que.len(), self.window_tokens());
+        let newest = len - 1;
+        let mut evict = Vec::new();
+        for (idx, msg) in self.msg_que.iter().enumerate().take(newest) {
+            if !self.over_limit(len, tokens) {
+                break;
+            }
+            if msg.pinned {
+                continue;
+            }
+            evict.push(idx);
+            len -= 1;
+            tokens -= self.cost(msg);
+        }
+        let mut evicted: Vec<AgentMessage> = evict
+            .into_iter()
+            .rev()
+            .filter_map(|idx| self.msg_que.remove(idx))
+            .collect();
+        evicted.reverse();
+        evicted
+    }
+
+    // Replaces runs of unpinned messages, oldest first, by summaries. Each run gives up the
+    // fewest messages that would make the window fit if their summary cost no more than an empty
+    // one, or all of them when that is not enough; the summarizer is asked once per run. A
+    // summary longer than that leaves the window over its limit, and the next pass folds it
+    // into a larger one. Runs whose summary would not shrink the window are left alone.
+    fn summarize_pass(&mut self, summarizer: &Summarizer) -> Vec<AgentMessage> {
+        let (mut len, mut tokens) = (self.msg_que.len(), self.window_tokens());
+        let mut plan = Vec::new();
+        for run in self.unpinned_runs() {
+            if !self.over_limit(len, tokens) {
+                break;
+            }
+            let first = &self.msg_que[run.start];
+            let reserve = self.cost(&summary(String::new(), first));
+            let mut freed = 0;
+            let mut k = run.len();
+            for (n, msg) in self.msg_que.range(run.clone()).enumerate() {
+                freed += self.cost(msg);
+                if !self.over_limit(len - n, (tokens + reserve).saturating_sub(freed)) {
+                    k = n + 1;
+                    break;
+                }
+            }
+            let msgs: Vec<AgentMessage> = self
+                .msg_que
+                .range(run.start..run.start + k)
+                .cloned()
+                .collect();
+            let freed: usize = msgs.iter().map(|msg| self.cost(msg)).sum();
+            let summary = summary(summarizer(&msgs), first);
+            let after = (len - k + 1, tokens - freed + self.cost(&summary));
+            if after.0 > len || after.1 > tokens || after == (len, tokens) {
+                continue;
+            }
+            plan.push((run.start, k, summary));
+            (len, tokens) = after;
+        }
+
+        let mut evicted = Vec::new();
+        // Back to front, so the earlier positions stay valid.
+        for (start, k, summary) in plan.into_iter().rev() {
+            let mut run: Vec<AgentMessage> = self.msg_que.drain(start..start + k).collect();
+            self.msg_que.insert(start, summary);
+            run.append(&mut evicted);
+            evicted = run;
+        }
+        evicted
+    }
+
+    // Index ranges of consecutive unpinned messages, the newest message excluded.
+    fn unpinned_runs(&self) -> Vec<Range<usize>> {
+        let newest = self.msg_que.len().saturating_sub(1);
+        let mut runs: Vec<Range<usize>> = Vec::new();
+        for (idx, msg) in self.msg_que.iter().enumerate().take(newest) {
+            if msg.pinned {
+                continue;
+            }
+            match runs.last_mut() {
+                Some(run) if run.end == idx => run.end += 1,
+                _ => runs.push(idx..idx + 1),
+            }
+        }
+        runs
     }
 
     pub fn messages(&self) -> &VecDeque<AgentMessage> {
@@ -61,4 +308,203 @@ impl AgentContext {
     pub fn last(&self) -> Option<&AgentMessage> {
         self.msg_que.back()
     }
+
+    pub fn query(&self) -> HistoryQuery<'_> {
+        HistoryQuery::new(self)
+    }
+
+    pub fn last_transcription(&self) -> Option<&str> {
+        self.query()
+            .kind(PayloadKind::Transcription)
+            .last()
+            .and_then(AgentMessage::text)
+    }
+
+    // Vision captions of the current run, oldest first.
+    pub fn vision_captions(&self) -> Vec<&str> {
+        self.query()
+            .current_run()
+            .kind(PayloadKind::VisionCaption)
+            .texts()
+    }
+}
+
+// A summary takes the place, and so the run, sequence number and time, of the oldest message
+// it covers.
+fn summary(text: String, first: &AgentMessage) -> AgentMessage {
+    let mut summary = AgentMessage::new(
+        first.run.clone(),
+        Sender::System,
+        MessagePayload::Text(text),
+    );
+    summary.seq = first.seq;
+    summary.timestamp = first.timestamp;
+    summary
+}
+
+fn pin_blob(blobs: &BlobManager, msg: &AgentMessage) {
+    if let Some(blob_ref) = msg.payload.blob_ref() {
+        // The blob may already be gone (quota eviction, finished run).
+        let _ = blobs.pin(blob_ref);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blob::{Blob, MemoryBlobStore};
+
+    fn text(text: &str) -> AgentMessage {
+        AgentMessage::new(RunId(1), Sender::User, MessagePayload::Text(text.into()))
+    }
+
+    fn texts(cx: &AgentContext) -> Vec<&str> {
+        cx.iter().filter_map(AgentMessage::text).collect()
+    }
+
+    #[test]
+    fn push_evicts_oldest_by_count() {
+        let mut cx = AgentContext::new(RunId(1), UserId(1), 2);
+        for msg in ["a", "b", "c"] {
+            cx.push(text(msg));
+        }
+        assert_eq!(texts(&cx), ["b", "c"]);
+    }
+
+    #[test]
+    fn token_budget_keeps_pinned_messages() {
+        // One token per message.
+        let mut cx = AgentContext::new(RunId(1), UserId(1), usize::MAX)
+            .with_token_budget(3)
+            .with_token_counter(|_| 1);
+        cx.push(text("system").pin());
+        for msg in ["a", "b", "c", "d"] {
+            cx.push(text(msg));
+        }
+
+        assert_eq!(texts(&cx), ["system", "c", "d"]);
+        assert_eq!(cx.tokens(), 3);
+    }
+
+    #[test]
+    fn summarizer_replaces_evicted_messages() {
+        let mut cx = AgentContext::new(RunId(1), UserId(1), usize::MAX)
+            .with_token_budget(3)
+            .with_token_counter(|_| 1)
+            .with_summarizer(|msgs| {
+                let parts: Vec<&str> = msgs.iter().filter_map(AgentMessage::text).collect();
+                format!("[{}]", parts.join(","))
+            });
+        cx.push(text("system").pin());
+        for msg in ["a", "b", "c", "d"] {
+            cx.push(text(msg));
+        }
+
+        // "a" and "b" were folded into a summary, then that summary and "c" into the next one.
+        assert_eq!(texts(&cx), ["system", "[[a,b],c]", "d"]);
+        assert!(matches!(cx.messages()[1].from, Sender::System));
+    }
+
+    #[test]
+    fn summarizer_keeps_pinned_messages_in_order() {
+        let mut cx = AgentContext::new(RunId(1), UserId(1), usize::MAX)
+            .with_token_budget(5)
+            .with_token_counter(|_| 1)
+            .with_summarizer(|msgs| {
+                let parts: Vec<&str> = msgs.iter().filter_map(AgentMessage::text).collect();
+                format!("[{}]", parts.join(","))
+            });
+        cx.push(text("system").pin());
+        for msg in ["a", "b"] {
+            cx.push(text(msg));
+        }
+        cx.push(text("photo").pin());
+        for msg in ["c", "d"] {
+            cx.push(text(msg));
+        }
+
+        // "a" and "b" sit before the pinned photo, so their summary does too.
+        assert_eq!(texts(&cx), ["system", "[a,b]", "photo", "c", "d"]);
+        assert_eq!(cx.messages()[1].seq, 1);
+    }
+
+    #[test]
+    fn summarizer_evicts_only_what_the_budget_needs() {
+        // One token per character; the summary is short.
+        let mut cx = AgentContext::new(RunId(1), UserId(1), usize::MAX)
+            .with_token_budget(10)
+            .with_token_counter(|msg| msg.text().map_or(0, str::len))
+            .with_summarizer(|msgs| format!("s{}", msgs.len()));
+        for msg in ["aaaaaaaa", "b", "cc"] {
+            cx.push(text(msg));
+        }
+
+        assert_eq!(texts(&cx), ["s1", "b", "cc"]);
+    }
+
+    #[test]
+    fn summarizer_is_asked_once_and_keeps_the_run() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        // The context has moved on to run 2; the summarized messages are from run 1.
+        let mut cx = AgentContext::new(RunId(2), UserId(1), 3).with_summarizer(move |msgs| {
+            counted.fetch_add(1, Ordering::Relaxed);
+            format!("s{}", msgs.len())
+        });
+        for msg in ["a", "b", "c", "d", "e"] {
+            cx.push(text(msg));
+        }
+
+        assert_eq!(texts(&cx), ["s2", "d", "e"]);
+        assert_eq!(cx.messages()[0].run, RunId(1));
+        // Once when "d" overflowed the window, once more when "e" did.
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn default_estimate_counts_text_and_blobs() {
+        assert_eq!(estimate_tokens(&text("12345678")), 2 + 4);
+        let image = AgentMessage::new(
+            RunId(1),
+            Sender::User,
+            MessagePayload::Image(blob::BlobRef(0)),
+        );
+        assert_eq!(estimate_tokens(&image), BLOB_TOKENS);
+    }
+
+    #[test]
+    fn push_releases_blobs_of_evicted_messages() {
+        let blobs = Arc::new(BlobManager::new(Arc::new(MemoryBlobStore::new())));
+        let mut cx = AgentContext::new(RunId(1), UserId(1), 1).with_blobs(blobs.clone());
+        let audio = blobs
+            .put(&RunId(1), &UserId(1), Blob::new(vec![0; 16]))
+            .unwrap();
+
+        cx.push(AgentMessage::new(
+            RunId(1),
+            Sender::User,
+            MessagePayload::Audio(audio),
+        ));
+        assert!(blobs.contains(audio));
+
+        cx.push(text("next"));
+        assert!(!blobs.contains(audio));
+        assert_eq!(blobs.usage(&UserId(1)), 0);
+    }
+
+    #[test]
+    fn pinned_message_keeps_its_blob_under_quota() {
+        let blobs =
+            Arc::new(BlobManager::new(Arc::new(MemoryBlobStore::new())).with_default_quota(16));
+        let (run, user) = (RunId(1), UserId(1));
+        let mut cx = AgentContext::new(run.clone(), user.clone(), 8).with_blobs(blobs.clone());
+        let photo = blobs.put(&run, &user, Blob::new(vec![0; 8])).unwrap();
+        cx.push(AgentMessage::new(run.clone(), Sender::User, MessagePayload::Image(photo)).pin());
+
+        blobs.put(&run, &user, Blob::new(vec![1; 8])).unwrap();
+        blobs.put(&run, &user, Blob::new(vec![2; 8])).unwrap();
+        assert!(blobs.contains(photo));
+    }
 }
@@ -0,0 +1,389 @@
+// Persistent `AgentContext` snapshots, so a conversation survives a backend restart or the
+// glasses reconnecting.
+use super::{
+    ids::{RunId, UserId},
+    message::AgentMessage,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    error::Error,
+    fmt::{self, Display},
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug)]
+pub enum HASessionError {
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl Display for HASessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HASessionError::Io(e) => write!(f, "session store failed: {}", e),
+            HASessionError::Json(e) => write!(f, "invalid session snapshot: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for HASessionError {
+    fn from(e: io::Error) -> Self {
+        HASessionError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for HASessionError {
+    fn from(e: serde_json::Error) -> Self {
+        HASessionError::Json(e)
+    }
+}
+
+impl Error for HASessionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HASessionError::Io(e) => Some(e),
+            HASessionError::Json(e) => Some(e),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ContextSnapshot {
+    pub run_id: RunId,
+    pub user_id: UserId,
+    pub capacity: usize,
+    pub token_budget: Option<usize>,
+    pub messages: Vec<AgentMessage>,
+    // Seconds since the Unix epoch.
+    pub saved_at: u64,
+}
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+// Which snapshots `prune` keeps. Unset limits keep everything.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RetentionPolicy {
+    pub max_age: Option<Duration>,
+    pub max_runs_per_user: Option<usize>,
+}
+
+impl RetentionPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn with_max_runs_per_user(mut self, runs: usize) -> Self {
+        self.max_runs_per_user = Some(runs);
+        self
+    }
+}
+
+// One snapshot per (user, run); saving the same run again replaces it.
+pub trait SessionStore {
+    fn save(&self, snapshot: &ContextSnapshot) -> Result<(), HASessionError>;
+    fn load(
+        &self,
+        user_id: &UserId,
+        run_id: &RunId,
+    ) -> Result<Option<ContextSnapshot>, HASessionError>;
+    // The most recently saved snapshot of the user.
+    fn load_latest(&self, user_id: &UserId) -> Result<Option<ContextSnapshot>, HASessionError>;
+    fn remove(&self, user_id: &UserId, run_id: &RunId) -> Result<(), HASessionError>;
+    // Deletes snapshots outside the retention policy. Returns how many were deleted.
+    fn prune(&self, policy: &RetentionPolicy) -> Result<usize, HASessionError>;
+}
+
+// Stores snapshots as JSON under `<root>/<user>/<run>.json`. When a retention policy is set,
+// the user's snapshots are pruned on every save.
+#[derive(Clone, Debug)]
+pub struct FileSessionStore {
+    root: PathBuf,
+    retention: Option<RetentionPolicy>,
+}
+
+impl FileSessionStore {
+    pub fn new(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        Ok(Self {
+            root,
+            retention: None,
+        })
+    }
+
+    pub fn with_retention(mut self, policy: RetentionPolicy) -> Self {
+        self.retention = Some(policy);
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn user_dir(&self, user_id: &UserId) -> PathBuf {
+        self.root.join(user_id.0.to_string())
+    }
+
+    fn path(&self, user_id: &UserId, run_id: &RunId) -> PathBuf {
+        self.user_dir(user_id).join(format!("{}.json", run_id.0))
+    }
+
+    fn read(path: &Path) -> Result<Option<ContextSnapshot>, HASessionError> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // All snapshots of the user directory, newest first. A snapshot that does not parse is
+    // renamed to `<run>.corrupt` and skipped, so it cannot hide the user's other sessions.
+    fn snapshots_in(dir: &Path) -> Result<Vec<(PathBuf, ContextSnapshot)>, HASessionError> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut snapshots = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            match Self::read(&path) {
+                Ok(Some(snapshot)) => snapshots.push((path, snapshot)),
+                Ok(None) => {}
+                Err(HASessionError::Json(_)) => fs::rename(&path, path.with_extension("corrupt"))?,
+                Err(e) => return Err(e),
+            }
+        }
+        snapshots.sort_by_key(|(_, snapshot)| Reverse((snapshot.saved_at, snapshot.run_id.0)));
+        Ok(snapshots)
+    }
+
+    fn prune_dir(dir: &Path, policy: &RetentionPolicy) -> Result<usize, HASessionError> {
+        let now = unix_now();
+        let mut removed = 0;
+        for (idx, (path, snapshot)) in Self::snapshots_in(dir)?.into_iter().enumerate() {
+            let too_many = policy.max_runs_per_user.is_some_and(|max| idx >= max);
+            let too_old = policy
+                .max_age
+                .is_some_and(|max_age| now.saturating_sub(snapshot.saved_at) > max_age.as_secs());
+            if too_many || too_old {
+                fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+impl SessionStore for FileSessionStore {
+    fn save(&self, snapshot: &ContextSnapshot) -> Result<(), HASessionError> {
+        let dir = self.user_dir(&snapshot.user_id);
+        fs::create_dir_all(&dir)?;
+        let path = self.path(&snapshot.user_id, &snapshot.run_id);
+        // Write, sync, then rename, so a crash never leaves a half-written snapshot behind.
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec(snapshot)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)?;
+        if let Some(policy) = &self.retention {
+            Self::prune_dir(&dir, policy)?;
+        }
+        Ok(())
+    }
+
+    fn load(
+        &self,
+        user_id: &UserId,
+        run_id: &RunId,
+    ) -> Result<Option<ContextSnapshot>, HASessionError> {
+        Self::read(&self.path(user_id, run_id))
+    }
+
+    fn load_latest(&self, user_id: &UserId) -> Result<Option<ContextSnapshot>, HASessionError> {
+        Ok(Self::snapshots_in(&self.user_dir(user_id))?
+            .into_iter()
+            .next()
+            .map(|(_, snapshot)| snapshot))
+    }
+
+    fn remove(&self, user_id: &UserId, run_id: &RunId) -> Result<(), HASessionError> {
+        match fs::remove_file(self.path(user_id, run_id)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn prune(&self, policy: &RetentionPolicy) -> Result<usize, HASessionError> {
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                removed += Self::prune_dir(&path, policy)?;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::{
+            AgentContext,
+            blob::{Blob, BlobRef, BlobStore, FileBlobStore},
+            message::{MessagePayload, Sender},
+        },
+        graph::NodeId,
+    };
+
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("hudagents-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        root
+    }
+
+    fn context(run: u64) -> AgentContext {
+        let mut cx = AgentContext::new(RunId(run), UserId(1), 8).with_token_budget(512);
+        cx.push(
+            AgentMessage::new(
+                RunId(run),
+                Sender::User,
+                MessagePayload::Text("system".into()),
+            )
+            .pin(),
+        );
+        cx.push(AgentMessage::new(
+            RunId(run),
+            Sender::User,
+            MessagePayload::Image(BlobRef(7)),
+        ));
+        cx.push(AgentMessage::new(
+            RunId(run),
+            Sender::Node(NodeId(2)),
+            MessagePayload::FinalAnswer("a mug".into()),
+        ));
+        cx
+    }
+
+    #[test]
+    fn resume_restores_latest_context_of_user() {
+        let root = temp_root("session-resume");
+        let store = FileSessionStore::new(&root).unwrap();
+        let mut older = context(1).snapshot();
+        older.saved_at -= 60;
+        store.save(&older).unwrap();
+        store.save(&context(2).snapshot()).unwrap();
+
+        let cx = AgentContext::resume(&store, &UserId(1)).unwrap().unwrap();
+        assert_eq!(cx.run_id, RunId(2));
+        assert_eq!(cx.token_budget, Some(512));
+        assert_eq!(cx.len(), 3);
+        assert!(cx.messages()[0].pinned);
+        assert!(matches!(
+            cx.messages()[1].payload,
+            MessagePayload::Image(BlobRef(7))
+        ));
+        assert!(matches!(cx.messages()[2].from, Sender::Node(NodeId(2))));
+
+        assert!(AgentContext::resume(&store, &UserId(2)).unwrap().is_none());
+        assert!(store.load(&UserId(1), &RunId(1)).unwrap().is_some());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn retention_drops_old_and_surplus_runs() {
+        let root = temp_root("session-retention");
+        let store = FileSessionStore::new(&root)
+            .unwrap()
+            .with_retention(RetentionPolicy::new().with_max_runs_per_user(2));
+        for run in 1..=3 {
+            let mut snapshot = context(run).snapshot();
+            snapshot.saved_at += run;
+            store.save(&snapshot).unwrap();
+        }
+        assert!(store.load(&UserId(1), &RunId(1)).unwrap().is_none());
+        assert!(store.load(&UserId(1), &RunId(3)).unwrap().is_some());
+
+        let mut stale = context(4).snapshot();
+        stale.saved_at -= 3_600;
+        // Saved without retention, otherwise the save itself would drop it as the oldest run.
+        FileSessionStore::new(&root).unwrap().save(&stale).unwrap();
+        let policy = RetentionPolicy::new().with_max_age(Duration::from_secs(60));
+        assert_eq!(store.prune(&policy).unwrap(), 1);
+        assert!(store.load(&UserId(1), &RunId(4)).unwrap().is_none());
+
+        store.remove(&UserId(1), &RunId(3)).unwrap();
+        store.remove(&UserId(1), &RunId(3)).unwrap();
+        assert_eq!(
+            store.load_latest(&UserId(1)).unwrap().unwrap().run_id,
+            RunId(2)
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn corrupt_snapshot_is_set_aside() {
+        let root = temp_root("session-corrupt");
+        let store = FileSessionStore::new(&root).unwrap();
+        store.save(&context(1).snapshot()).unwrap();
+        let broken = store.path(&UserId(1), &RunId(2));
+        fs::write(&broken, b"{\"run_id\":").unwrap();
+
+        let latest = store.load_latest(&UserId(1)).unwrap().unwrap();
+        assert_eq!(latest.run_id, RunId(1));
+        assert!(!broken.exists());
+        assert!(broken.with_extension("corrupt").exists());
+        assert_eq!(
+            store
+                .prune(&RetentionPolicy::new().with_max_runs_per_user(1))
+                .unwrap(),
+            0
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn resumed_blobs_survive_a_blob_store_restart() {
+        let root = temp_root("session-blob-restart");
+        let blob_root = root.join("blobs");
+        let blobs = FileBlobStore::new(&blob_root).unwrap();
+        let store = FileSessionStore::new(root.join("sessions")).unwrap();
+        let photo = blobs
+            .put(Blob::new(vec![3; 16]).with_mime("image/jpeg"))
+            .unwrap();
+        let mut cx = AgentContext::new(RunId(1), UserId(1), 8);
+        cx.push(AgentMessage::new(
+            RunId(1),
+            Sender::User,
+            MessagePayload::Image(photo),
+        ));
+        store.save(&cx.snapshot()).unwrap();
+        drop(blobs);
+
+        let blobs = FileBlobStore::new(&blob_root).unwrap();
+        let cx = AgentContext::resume(&store, &UserId(1)).unwrap().unwrap();
+        let blob_ref = cx.messages()[0].payload.blob_ref().unwrap();
+        let got = blobs.get(blob_ref).unwrap();
+        assert_eq!(&got.bytes[..], &[3; 16]);
+        assert_eq!(got.mime, Some("image/jpeg"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
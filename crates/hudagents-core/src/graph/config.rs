@@ -0,0 +1,420 @@
+// Declarative graph definitions loaded from TOML.
+//
+// [[nodes]]
+// name = "listen"
+// kind = "speech_to_text"
+// params = { model_path = "models/base.en.bin" }
+//
+// [[nodes]]
+// name = "answer"
+// kind = "echo"
+//
+// [[edges]]
+// from = "listen"
+// to = "answer"
+// when_contains = "what am I looking at"   # optional
+//
+// [[loops]]                                # optional
+// from = "answer"
+// to = "answer"
+// max_iterations = 3
+// until_contains = "done"                  # optional
+use super::{Edge, Graph, GraphBuilder, HAGraphError, NodeId};
+use crate::agent::{
+    HAAgentError,
+    registry::{AgentConfig, AgentRegistry},
+};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt::{self, Debug, Display},
+    fs,
+    path::Path,
+};
+
+#[derive(Debug)]
+pub enum HAConfigError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    InvalidParam { node: String, key: String },
+    DuplicateNode(String),
+    UnknownNode(String),
+    Agent { node: String, source: HAAgentError },
+    Graph(HAGraphError),
+}
+
+impl Display for HAConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HAConfigError::Io(e) => write!(f, "failed to read graph config: {}", e),
+            HAConfigError::Parse(e) => write!(f, "failed to parse graph config: {}", e),
+            HAConfigError::InvalidParam { node, key } => write!(
+                f,
+                "node `{}`: parameter `{}` must be a string, number or boolean",
+                node, key
+            ),
+            HAConfigError::DuplicateNode(name) => write!(f, "duplicate node name: {}", name),
+            HAConfigError::UnknownNode(name) => write!(f, "unknown node name: {}", name),
+            HAConfigError::Agent { node, source } => {
+                write!(f, "failed to create agent for node `{}`: {}", node, source)
+            }
+            HAConfigError::Graph(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for HAConfigError {
+    fn from(e: std::io::Error) -> Self {
+        HAConfigError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for HAConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        HAConfigError::Parse(e)
+    }
+}
+
+impl From<HAGraphError> for HAConfigError {
+    fn from(e: HAGraphError) -> Self {
+        HAConfigError::Graph(e)
+    }
+}
+
+impl Error for HAConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HAConfigError::Io(e) => Some(e),
+            HAConfigError::Parse(e) => Some(e),
+            HAConfigError::Agent { source, .. } => Some(source),
+            HAConfigError::Graph(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeConfig {
+    pub name: String,
+    pub kind: String,
+    #[serde(default)]
+    pub params: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoopConfig {
+    pub from: String,
+    pub to: String,
+    pub max_iterations: usize,
+    #[serde(default)]
+    pub until_contains: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GraphConfig {
+    pub nodes: Vec<NodeConfig>,
+    #[serde(default)]
+    pub edges: Vec<Edge>,
+    #[serde(default)]
+    pub loops: Vec<LoopConfig>,
+}
+
+impl GraphConfig {
+    pub fn from_toml_str(input: &str) -> Result<Self, HAConfigError> {
+        Ok(toml::from_str(input)?)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, HAConfigError> {
+        Self::from_toml_str(&fs::read_to_string(path)?)
+    }
+
+    // Creates every node through `registry` and wires the edges by name into a validated graph.
+    pub fn build(&self, registry: &AgentRegistry) -> Result<Graph, HAConfigError> {
+        let mut builder = GraphBuilder::new();
+        let mut ids: HashMap<&str, NodeId> = HashMap::new();
+        for node in &self.nodes {
+            if ids.contains_key(node.name.as_str()) {
+                return Err(HAConfigError::DuplicateNode(node.name.clone()));
+            }
+            let config = agent_config(node)?;
+            let agent = registry
+                .create(&node.kind, &node.name, &config)
+                .map_err(|source| HAConfigError::Agent {
+                    node: node.name.clone(),
+                    source,
+                })?;
+            ids.insert(&node.name, builder.add_node(node.name.clone(), agent));
+        }
+
+        let lookup = |name: &str| {
+            ids.get(name)
+                .copied()
+                .ok_or_else(|| HAConfigError::UnknownNode(name.to_string()))
+        };
+        for edge in &self.edges {
+            let (from, to) = (lookup(&edge.from)?, lookup(&edge.to)?);
+            match &edge.when_contains {
+                Some(needle) => {
+                    let needle = needle.clone();
+                    builder.add_conditional_edge(from, to, move |output, _| {
+                        output.text().contains(needle.as_str())
+                    })?
+                }
+                None => builder.add_edge(from, to)?,
+            }
+        }
+        for edge in &self.loops {
+            let (from, to) = (lookup(&edge.from)?, lookup(&edge.to)?);
+            let until = edge.until_contains.clone();
+            builder.add_loop(from, to, edge.max_iterations, move |output| {
+                until
+                    .as_deref()
+                    .is_some_and(|needle| output.text().contains(needle))
+            })?;
+        }
+        Ok(builder.build()?)
+    }
+}
+
+pub fn load_graph(
+    path: impl AsRef<Path>,
+    registry: &AgentRegistry,
+) -> Result<Graph, HAConfigError> {
+    GraphConfig::from_file(path)?.build(registry)
+}
+
+// Scalars are passed to agents as strings; tables and arrays are rejected.
+fn agent_config(node: &NodeConfig) -> Result<AgentConfig, HAConfigError> {
+    node.params
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                toml::Value::String(s) => s.clone(),
+                toml::Value::Integer(i) => i.to_string(),
+                toml::Value::Float(x) => x.to_string(),
+                toml::Value::Boolean(b) => b.to_string(),
+                _ => {
+                    return Err(HAConfigError::InvalidParam {
+                        node: node.name.clone(),
+                        key: key.clone(),
+                    });
+                }
+            };
+            Ok((key.clone(), value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        agent::{Agent, AgentInput, AgentOutput, registry::required},
+        runtime::{GraphRunner, NodeStatus},
+    };
+    use std::sync::Arc;
+
+    struct SuffixAgent(String);
+
+    impl Agent for SuffixAgent {
+        fn id(&self) -> &str {
+            "suffix"
+        }
+
+        fn call(&self, agent_input: AgentInput) -> Result<AgentOutput, HAAgentError> {
+            match agent_input {
+                AgentInput::Text(text) => Ok(AgentOutput::FinalAnswer(text + &self.0)),
+                _ => Err(HAAgentError::InvalidInput("expected text input".into())),
+            }
+        }
+    }
+
+    fn registry() -> AgentRegistry {
+        let mut registry = AgentRegistry::new();
+        registry.register("suffix", |_, config| {
+            Ok(Arc::new(SuffixAgent(
+                required(config, "suffix")?.to_string(),
+            )))
+        });
+        registry
+    }
+
+    const PIPELINE: &str = r#"
+        [[nodes]]
+        name = "listen"
+        kind = "suffix"
+        params = { suffix = "?" }
+
+        [[nodes]]
+        name = "vision"
+        kind = "suffix"
+        params = { suffix = 1 }
+
+        [[nodes]]
+        name = "chat"
+        kind = "suffix"
+        params = { suffix = true }
+
+        [[edges]]
+        from = "listen"
+        to = "vision"
+        when_contains = "what am I looking at"
+
+        [[edges]]
+        from = "listen"
+        to = "chat"
+    "#;
+
+    #[test]
+    fn build_wires_nodes_and_edges_by_name() {
+        let graph = GraphConfig::from_toml_str(PIPELINE)
+            .unwrap()
+            .build(&registry())
+            .unwrap();
+        assert_eq!(
+            graph.layers,
+            vec![vec![NodeId(0)], vec![NodeId(1), NodeId(2)]]
+        );
+
+        let report = GraphRunner::new(graph)
+            .unwrap()
+            .run(AgentInput::Text("hello".into()))
+            .unwrap();
+        assert!(matches!(
+            report.status(NodeId(1)),
+            Some(NodeStatus::Skipped)
+        ));
+        assert_eq!(report.output(NodeId(2)).unwrap().text(), "hello?true");
+    }
+
+    #[test]
+    fn build_supports_loops() {
+        let config = GraphConfig::from_toml_str(
+            r#"
+            [[nodes]]
+            name = "grow"
+            kind = "suffix"
+            params = { suffix = "a" }
+
+            [[loops]]
+            from = "grow"
+            to = "grow"
+            max_iterations = 5
+            until_contains = "aaa"
+            "#,
+        )
+        .unwrap();
+
+        let runner = GraphRunner::new(config.build(&registry()).unwrap()).unwrap();
+        let report = runner.run(AgentInput::Text(String::new())).unwrap();
+        assert_eq!(report.output(NodeId(0)).unwrap().text(), "aaa");
+    }
+
+    #[test]
+    fn build_rejects_unknown_edge_node() {
+        let config = GraphConfig::from_toml_str(
+            r#"
+            [[nodes]]
+            name = "a"
+            kind = "suffix"
+            params = { suffix = "a" }
+
+            [[edges]]
+            from = "a"
+            to = "b"
+            "#,
+        )
+        .unwrap();
+
+        let err = config.build(&registry()).err().expect("expected error");
+        assert!(matches!(err, HAConfigError::UnknownNode(name) if name == "b"));
+    }
+
+    #[test]
+    fn build_rejects_duplicate_node_names() {
+        let config = GraphConfig::from_toml_str(
+            r#"
+            [[nodes]]
+            name = "a"
+            kind = "suffix"
+            params = { suffix = "a" }
+
+            [[nodes]]
+            name = "a"
+            kind = "suffix"
+            params = { suffix = "b" }
+            "#,
+        )
+        .unwrap();
+
+        let err = config.build(&registry()).err().expect("expected error");
+        assert!(matches!(err, HAConfigError::DuplicateNode(_)));
+    }
+
+    #[test]
+    fn build_reports_agent_errors_with_node_name() {
+        let config = GraphConfig::from_toml_str(
+            r#"
+            [[nodes]]
+            name = "a"
+            kind = "nope"
+            "#,
+        )
+        .unwrap();
+
+        let err = config.build(&registry()).err().expect("expected error");
+        assert!(matches!(
+            err,
+            HAConfigError::Agent { node, source: HAAgentError::UnknownKind(_) } if node == "a"
+        ));
+    }
+
+    #[test]
+    fn build_rejects_table_params() {
+        let config = GraphConfig::from_toml_str(
+            r#"
+            [[nodes]]
+            name = "a"
+            kind = "suffix"
+            params = { suffix = { nested = 1 } }
+            "#,
+        )
+        .unwrap();
+
+        let err = config.build(&registry()).err().expect("expected error");
+        assert!(matches!(err, HAConfigError::InvalidParam { .. }));
+    }
+
+    #[test]
+    fn from_toml_str_rejects_unknown_fields() {
+        // A misspelt `when_contains` must not leave an unconditional edge behind.
+        let edge = r#"
+            [[nodes]]
+            name = "a"
+            kind = "echo"
+
+            [[edges]]
+            from = "a"
+            to = "a"
+            when_contain = "yes"
+        "#;
+        let node = "[[nodes]]\nname = \"a\"\nkind = \"echo\"\nparam = {}\n";
+        let top = "nodes = []\nedge = []\n";
+        let looped =
+            "nodes = []\n[[loops]]\nfrom = \"a\"\nto = \"a\"\nmax_iterations = 2\nuntil = \"x\"\n";
+        for input in [edge, node, top, looped] {
+            let err = GraphConfig::from_toml_str(input).unwrap_err();
+            assert!(matches!(err, HAConfigError::Parse(_)), "{input}");
+        }
+    }
+
+    #[test]
+    fn from_toml_str_rejects_malformed_input() {
+        let err = GraphConfig::from_toml_str("[[nodes]]\nname = ").unwrap_err();
+        assert!(matches!(err, HAConfigError::Parse(_)));
+    }
+}
@@ -0,0 +1,228 @@
+// Graphviz DOT and Mermaid rendering of graphs, optionally coloured by a run report.
+use super::{Graph, NodeId};
+use crate::runtime::{NodeStatus, RunReport};
+use std::fmt::Write;
+
+// Mermaid class name and fill colour per node status.
+const MERMAID_CLASSES: [(&str, &str); 3] = [
+    ("ok", "#b7e4b0"),
+    ("failed", "#f4a6a6"),
+    ("skipped", "#dddddd"),
+];
+
+// (Mermaid class name, DOT fill colour)
+fn status_style(status: &NodeStatus) -> (&'static str, &'static str) {
+    match status {
+        NodeStatus::Completed(_) => ("ok", "palegreen"),
+        NodeStatus::Failed(_) => ("failed", "lightcoral"),
+        NodeStatus::Skipped => ("skipped", "lightgrey"),
+    }
+}
+
+impl Graph {
+    pub fn to_dot(&self) -> String {
+        self.render_dot(None)
+    }
+
+    pub fn to_dot_with_report(&self, report: &RunReport) -> String {
+        self.render_dot(Some(report))
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        self.render_mermaid(None)
+    }
+
+    pub fn to_mermaid_with_report(&self, report: &RunReport) -> String {
+        self.render_mermaid(Some(report))
+    }
+
+    // Every layer becomes a cluster. Conditional edges are dashed, loop back-edges dotted.
+    fn render_dot(&self, report: Option<&RunReport>) -> String {
+        let mut out = String::from("digraph hudagents {\n    rankdir=TB;\n    node [shape=box];\n");
+        for (i, layer) in self.layers.iter().enumerate() {
+            let _ = writeln!(out, "    subgraph cluster_layer_{i} {{");
+            let _ = writeln!(out, "        label=\"layer {i}\";");
+            for &node in layer {
+                let label = dot_escape(&self.node_label(node, "\n"));
+                let _ = write!(out, "        n{} [label=\"{label}\"", node.0);
+                if let Some(status) = report.and_then(|r| r.status(node)) {
+                    let (_, fill) = status_style(status);
+                    let _ = write!(out, ", style=filled, fillcolor={fill}");
+                }
+                out.push_str("];\n");
+            }
+            out.push_str("    }\n");
+        }
+        for (from, targets) in self.out.iter().enumerate() {
+            for &to in targets {
+                let _ = write!(out, "    n{from} -> n{}", to.0);
+                if self.conditions.contains_key(&(NodeId(from), to)) {
+                    out.push_str(" [style=dashed, label=\"if\"]");
+                }
+                out.push_str(";\n");
+            }
+        }
+        for edge in &self.loops {
+            let _ = writeln!(
+                out,
+                "    n{} -> n{} [style=dotted, constraint=false, label=\"loop <= {}\"];",
+                edge.from.0, edge.to.0, edge.max_iterations
+            );
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    // Every layer becomes a subgraph. Conditional edges are dotted with an `if` label,
+    // loop back-edges are dotted with their iteration limit.
+    fn render_mermaid(&self, report: Option<&RunReport>) -> String {
+        let mut out = String::from("flowchart TD\n");
+        let mut classes: Vec<(&str, NodeId)> = Vec::new();
+        for (i, layer) in self.layers.iter().enumerate() {
+            let _ = writeln!(out, "    subgraph layer_{i} [\"layer {i}\"]");
+            for &node in layer {
+                let label = mermaid_escape(&self.node_label(node, "<br/>"));
+                let _ = writeln!(out, "        n{}[\"{label}\"]", node.0);
+                if let Some(status) = report.and_then(|r| r.status(node)) {
+                    classes.push((status_style(status).0, node));
+                }
+            }
+            out.push_str("    end\n");
+        }
+        for (from, targets) in self.out.iter().enumerate() {
+            for &to in targets {
+                if self.conditions.contains_key(&(NodeId(from), to)) {
+                    let _ = writeln!(out, "    n{from} -.->|if| n{}", to.0);
+                } else {
+                    let _ = writeln!(out, "    n{from} --> n{}", to.0);
+                }
+            }
+        }
+        for edge in &self.loops {
+            let _ = writeln!(
+                out,
+                "    n{} -.->|\"loop <= {}\"| n{}",
+                edge.from.0, edge.max_iterations, edge.to.0
+            );
+        }
+        if report.is_some() {
+            for (name, fill) in MERMAID_CLASSES {
+                let _ = writeln!(out, "    classDef {name} fill:{fill}");
+            }
+            for (name, node) in classes {
+                let _ = writeln!(out, "    class n{} {name}", node.0);
+            }
+        }
+        out
+    }
+
+    fn node_label(&self, node: NodeId, line_break: &str) -> String {
+        let n = &self.nodes[node.0];
+        let description = n.worker.describe();
+        if description == n.name {
+            n.name.clone()
+        } else {
+            format!("{}{line_break}{description}", n.name)
+        }
+    }
+}
+
+fn dot_escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn mermaid_escape(label: &str) -> String {
+    label.replace('"', "#quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        agent::{Agent, AgentInput, AgentOutput, HAAgentError},
+        context::blob::Blob,
+        graph::GraphBuilder,
+        runtime::GraphRunner,
+    };
+    use std::sync::Arc;
+
+    struct TestAgent(&'static str);
+
+    impl Agent for TestAgent {
+        fn id(&self) -> &str {
+            self.0
+        }
+
+        fn call(&self, agent_input: AgentInput) -> Result<AgentOutput, HAAgentError> {
+            match agent_input {
+                AgentInput::Text(text) => Ok(AgentOutput::FinalAnswer(text)),
+                _ => Err(HAAgentError::InvalidInput("expected text input".into())),
+            }
+        }
+
+        fn describe(&self) -> String {
+            format!("TestAgent(\"{}\")", self.0)
+        }
+    }
+
+    fn graph() -> Graph {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("listen", Arc::new(TestAgent("a")));
+        let c = b.add_node("vision", Arc::new(TestAgent("c")));
+        let d = b.add_node("answer", Arc::new(TestAgent("d")));
+        b.add_edge(a, c).unwrap();
+        b.add_conditional_edge(c, d, |_, _| false).unwrap();
+        b.add_loop(c, a, 3, |_| true).unwrap();
+        b.build().unwrap()
+    }
+
+    #[test]
+    fn to_dot_renders_layers_edges_and_descriptions() {
+        let dot = graph().to_dot();
+
+        assert!(dot.starts_with("digraph hudagents {"));
+        assert!(dot.contains("subgraph cluster_layer_2 {"));
+        assert!(dot.contains(r#"n0 [label="listen\nTestAgent(\"a\")"];"#));
+        assert!(dot.contains("n0 -> n1;"));
+        assert!(dot.contains(r#"n1 -> n2 [style=dashed, label="if"];"#));
+        assert!(dot.contains(r#"n1 -> n0 [style=dotted, constraint=false, label="loop <= 3"];"#));
+        assert!(!dot.contains("fillcolor"));
+    }
+
+    #[test]
+    fn to_dot_with_report_colours_nodes_by_status() {
+        let runner = GraphRunner::new(graph()).unwrap();
+        let report = runner.run(AgentInput::Text("hi".into())).unwrap();
+
+        let dot = runner.graph().to_dot_with_report(&report);
+        assert!(dot.contains(
+            r#"n0 [label="listen\nTestAgent(\"a\")", style=filled, fillcolor=palegreen];"#
+        ));
+        assert!(dot.contains(
+            r#"n2 [label="answer\nTestAgent(\"d\")", style=filled, fillcolor=lightgrey];"#
+        ));
+    }
+
+    #[test]
+    fn to_mermaid_renders_layers_edges_and_statuses() {
+        let runner = GraphRunner::new(graph()).unwrap();
+        let plain = runner.graph().to_mermaid();
+
+        assert!(plain.starts_with("flowchart TD\n"));
+        assert!(plain.contains("subgraph layer_0 [\"layer 0\"]"));
+        assert!(plain.contains("n0[\"listen<br/>TestAgent(#quot;a#quot;)\"]"));
+        assert!(plain.contains("n0 --> n1"));
+        assert!(plain.contains("n1 -.->|if| n2"));
+        assert!(plain.contains("n1 -.->|\"loop <= 3\"| n0"));
+        assert!(!plain.contains("classDef"));
+
+        let report = runner.run(AgentInput::Audio(Blob::new(vec![]))).unwrap();
+        let traced = runner.graph().to_mermaid_with_report(&report);
+        assert!(traced.contains("classDef failed fill:#f4a6a6"));
+        assert!(traced.contains("class n0 failed"));
+        assert!(traced.contains("class n2 skipped"));
+    }
+}
@@ -1,6 +1,13 @@
-use crate::agent::Agent;
+pub mod config;
+pub mod export;
+use crate::{
+    agent::{Agent, AgentOutput, AsyncAgent},
+    context::AgentContext,
+};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
+    error::Error,
     fmt::{self, Debug, Display},
     sync::Arc,
 };
@@ -22,29 +29,93 @@ impl Display for HAGraphError {
     }
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+impl Error for HAGraphError {}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct NodeId(pub usize);
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct LoopId(pub usize);
+
+#[derive(Clone)]
+pub enum Worker {
+    Blocking(Arc<dyn Agent + Send + Sync>),
+    Async(Arc<dyn AsyncAgent + Send + Sync>),
+}
+
+impl Worker {
+    pub fn id(&self) -> &str {
+        match self {
+            Worker::Blocking(agent) => agent.id(),
+            Worker::Async(agent) => agent.id(),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Worker::Blocking(agent) => agent.describe(),
+            Worker::Async(agent) => agent.describe(),
+        }
+    }
+}
+
 pub struct Node {
     pub name: String,
-    pub worker: Arc<dyn Agent + Send + Sync>,
+    pub worker: Worker,
 }
 
+// Edge between two nodes referenced by name, as written in graph config files.
+// `when_contains` turns it into a conditional edge that only fires when the upstream output
+// contains the given text.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Edge {
     pub from: String,
     pub to: String,
+    #[serde(default)]
+    pub when_contains: Option<String>,
+}
+
+// Decides whether a conditional edge fires from the upstream output and a read-only snapshot
+// of the context, taken when the layer of the edge's target starts.
+pub type EdgePredicate = Arc<dyn Fn(&AgentOutput, &AgentContext) -> bool + Send + Sync>;
+
+// Decides from the output of a loop source whether the loop exits.
+pub type LoopExit = Arc<dyn Fn(&AgentOutput) -> bool + Send + Sync>;
+
+// A back-edge from `from` to `to` that re-runs the loop body (every node on a path from `to` to
+// `from`) until `exit` accepts the output of `from` or the body ran `max_iterations` times.
+// Back-edges are kept out of `out`, so the rest of the graph is still validated as a DAG.
+#[derive(Clone)]
+pub struct LoopEdge {
+    pub from: NodeId,
+    pub to: NodeId,
+    pub max_iterations: usize,
+    pub exit: LoopExit,
+    pub body: Vec<NodeId>,
 }
 
 pub struct Graph {
     pub nodes: Vec<Node>,
     pub out: Vec<Vec<NodeId>>,
     pub layers: Vec<Vec<NodeId>>,
+    // Edges without an entry here always fire.
+    pub conditions: HashMap<(NodeId, NodeId), EdgePredicate>,
+    pub loops: Vec<LoopEdge>,
 }
 
 pub struct GraphBuilder {
     pub nodes: Vec<Node>,
     pub out: Vec<Vec<NodeId>>,
     pub indegree: Vec<usize>,
+    pub conditions: HashMap<(NodeId, NodeId), EdgePredicate>,
+    pub loops: Vec<LoopEdge>,
+}
+
+impl Default for GraphBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GraphBuilder {
@@ -53,6 +124,8 @@ impl GraphBuilder {
             nodes: vec![],
             out: vec![],
             indegree: vec![],
+            conditions: HashMap::new(),
+            loops: vec![],
         }
     }
 
@@ -61,11 +134,20 @@ impl GraphBuilder {
         name: impl Into<String>,
         worker: Arc<dyn Agent + Send + Sync>,
     ) -> NodeId {
+        self.push_node(name.into(), Worker::Blocking(worker))
+    }
+
+    pub fn add_async_node(
+        &mut self,
+        name: impl Into<String>,
+        worker: Arc<dyn AsyncAgent + Send + Sync>,
+    ) -> NodeId {
+        self.push_node(name.into(), Worker::Async(worker))
+    }
+
+    fn push_node(&mut self, name: String, worker: Worker) -> NodeId {
         let id = NodeId(self.nodes.len());
-        self.nodes.push(Node {
-            name: name.into(),
-            worker,
-        });
+        self.nodes.push(Node { name, worker });
         self.out.push(Vec::new());
         self.indegree.push(0);
         id
@@ -79,21 +161,110 @@ impl GraphBuilder {
                 from.0, to.0, n
             )));
         }
+        if self.out[from.0].contains(&to) {
+            return Err(HAGraphError::InvalidGraph(format!(
+                "duplicate edge: from={} to={}",
+                from.0, to.0
+            )));
+        }
         self.out[from.0].push(to);
         self.indegree[to.0] += 1;
         Ok(())
     }
 
-    pub fn build(self) -> Result<Graph, HAGraphError> {
+    // Adds an edge that only fires when `predicate` accepts the output of `from` and the
+    // context. A node whose inbound edges all stay silent is skipped instead of executed.
+    pub fn add_conditional_edge(
+        &mut self,
+        from: NodeId,
+        to: NodeId,
+        predicate: impl Fn(&AgentOutput, &AgentContext) -> bool + Send + Sync + 'static,
+    ) -> Result<(), HAGraphError> {
+        self.add_edge(from, to)?;
+        self.conditions.insert((from, to), Arc::new(predicate));
+        Ok(())
+    }
+
+    // Adds a back-edge from `from` to its ancestor `to` (or to itself). The loop body runs again,
+    // fed with the output of `from`, until `exit` accepts that output or the body has run
+    // `max_iterations` times. The loop body is resolved in `build`.
+    pub fn add_loop(
+        &mut self,
+        from: NodeId,
+        to: NodeId,
+        max_iterations: usize,
+        exit: impl Fn(&AgentOutput) -> bool + Send + Sync + 'static,
+    ) -> Result<LoopId, HAGraphError> {
+        let n = self.nodes.len();
+        if from.0 >= n || to.0 >= n {
+            return Err(HAGraphError::InvalidNodeId(format!(
+                "Invalid loop: from={} to={} (node count={})",
+                from.0, to.0, n
+            )));
+        }
+        if max_iterations == 0 {
+            return Err(HAGraphError::InvalidGraph(format!(
+                "loop from={} to={} must allow at least one iteration",
+                from.0, to.0
+            )));
+        }
+        let id = LoopId(self.loops.len());
+        self.loops.push(LoopEdge {
+            from,
+            to,
+            max_iterations,
+            exit: Arc::new(exit),
+            body: Vec::new(),
+        });
+        Ok(id)
+    }
+
+    pub fn build(mut self) -> Result<Graph, HAGraphError> {
         let layers = kahn_layers(self.nodes.len(), &self.out, &self.indegree)?;
+        for edge in &mut self.loops {
+            edge.body = loop_body(&self.out, edge.to, edge.from);
+            if edge.body.is_empty() {
+                return Err(HAGraphError::InvalidGraph(format!(
+                    "loop target {} is not an ancestor of loop source {}",
+                    edge.to.0, edge.from.0
+                )));
+            }
+        }
         Ok(Graph {
             nodes: self.nodes,
             out: self.out,
             layers,
+            conditions: self.conditions,
+            loops: self.loops,
         })
     }
 }
 
+// Nodes reachable from `head` that can also reach `tail`, in index order.
+// Empty when `tail` is not reachable from `head`.
+fn loop_body(out: &[Vec<NodeId>], head: NodeId, tail: NodeId) -> Vec<NodeId> {
+    let reachable = |start: NodeId| {
+        let mut seen = vec![false; out.len()];
+        let mut stack = vec![start];
+        while let Some(node) = stack.pop() {
+            if !seen[node.0] {
+                seen[node.0] = true;
+                stack.extend(out[node.0].iter().copied());
+            }
+        }
+        seen
+    };
+
+    let from_head = reachable(head);
+    if !from_head[tail.0] {
+        return Vec::new();
+    }
+    (0..out.len())
+        .filter(|&i| from_head[i] && reachable(NodeId(i))[tail.0])
+        .map(NodeId)
+        .collect()
+}
+
 fn kahn_layers(
     n: usize,
     out: &[Vec<NodeId>],
@@ -151,7 +322,10 @@ fn kahn_layers(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::agent::{AgentInput, AgentOutput, HAAgentError};
+    use crate::{
+        agent::{AgentInput, AgentOutput, HAAgentError},
+        context::ids::{RunId, UserId},
+    };
 
     struct TestAgent(&'static str);
 
@@ -234,6 +408,110 @@ mod tests {
         }
     }
 
+    #[test]
+    fn conditional_edge_is_layered_like_plain_edge() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("A", agent("a"));
+        let c = b.add_node("C", agent("c"));
+
+        b.add_conditional_edge(a, c, |output, _| output.text().is_empty())
+            .unwrap();
+
+        let g = b.build().unwrap();
+        assert_eq!(g.layers, vec![vec![a], vec![c]]);
+        let predicate = &g.conditions[&(a, c)];
+        let cx = AgentContext::new(RunId(1), UserId(1), 8);
+        assert!(predicate(&AgentOutput::FinalAnswer(String::new()), &cx));
+        assert!(!predicate(&AgentOutput::FinalAnswer("x".into()), &cx));
+    }
+
+    #[test]
+    fn add_conditional_edge_rejects_invalid_node_id() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("A", agent("a"));
+
+        let err = b
+            .add_conditional_edge(NodeId(7), a, |_, _| true)
+            .unwrap_err();
+        assert!(matches!(err, HAGraphError::InvalidNodeId(_)));
+        assert!(b.conditions.is_empty());
+    }
+
+    #[test]
+    fn add_edge_rejects_duplicate_edges() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("A", agent("a"));
+        let c = b.add_node("C", agent("c"));
+        b.add_conditional_edge(a, c, |_, _| true).unwrap();
+
+        let err = b.add_conditional_edge(a, c, |_, _| false).unwrap_err();
+        assert!(matches!(err, HAGraphError::InvalidGraph(_)));
+        assert!(matches!(
+            b.add_edge(a, c),
+            Err(HAGraphError::InvalidGraph(_))
+        ));
+        assert_eq!(b.out[a.0], vec![c]);
+        assert_eq!(b.indegree[c.0], 1);
+        // The first predicate is kept.
+        let cx = AgentContext::new(RunId(1), UserId(1), 8);
+        assert!(b.conditions[&(a, c)](
+            &AgentOutput::FinalAnswer(String::new()),
+            &cx
+        ));
+    }
+
+    #[test]
+    fn loop_keeps_dag_layers_and_resolves_body() {
+        let mut b = GraphBuilder::new();
+        let ask = b.add_node("ask", agent("ask"));
+        let tool = b.add_node("tool", agent("tool"));
+        let side = b.add_node("side", agent("side"));
+        let check = b.add_node("check", agent("check"));
+        b.add_edge(ask, tool).unwrap();
+        b.add_edge(ask, side).unwrap();
+        b.add_edge(tool, check).unwrap();
+
+        let id = b.add_loop(check, ask, 3, |_| true).unwrap();
+
+        let g = b.build().unwrap();
+        assert_eq!(id, LoopId(0));
+        assert_eq!(g.layers, vec![vec![ask], vec![tool, side], vec![check]]);
+        assert_eq!(g.loops[0].body, vec![ask, tool, check]);
+    }
+
+    #[test]
+    fn self_loop_body_is_the_node() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("A", agent("a"));
+        b.add_loop(a, a, 2, |_| true).unwrap();
+
+        let g = b.build().unwrap();
+        assert_eq!(g.loops[0].body, vec![a]);
+    }
+
+    #[test]
+    fn build_rejects_loop_to_non_ancestor() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("A", agent("a"));
+        let c = b.add_node("C", agent("c"));
+        b.add_edge(a, c).unwrap();
+        b.add_loop(a, c, 2, |_| true).unwrap();
+
+        match b.build() {
+            Err(err) => assert!(matches!(err, HAGraphError::InvalidGraph(_))),
+            Ok(_) => panic!("expected invalid loop error"),
+        }
+    }
+
+    #[test]
+    fn add_loop_rejects_zero_iterations() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("A", agent("a"));
+
+        let err = b.add_loop(a, a, 0, |_| true).unwrap_err();
+        assert!(matches!(err, HAGraphError::InvalidGraph(_)));
+    }
+
     #[test]
     fn add_edge_rejects_invalid_node_id() {
         let mut b = GraphBuilder::new();
@@ -1 +1,1225 @@
 // Runtime
+use crate::{
+    agent::{AgentInput, AgentOutput, CallContext, CombinedInput, HAAgentError, UpstreamOutput},
+    context::{
+        AgentContext, Control,
+        ids::{RunId, UserId},
+        message::{AgentMessage, MessagePayload, Sender},
+    },
+    graph::{Graph, HAGraphError, LoopId, NodeId, Worker},
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Debug, Display},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    sync::Semaphore,
+    task::{JoinError, JoinSet},
+};
+
+#[derive(Debug)]
+pub enum HARuntimeError {
+    Graph(HAGraphError),
+    Runtime(std::io::Error),
+    NodePanicked(String),
+    InvalidControl(String),
+}
+
+impl Display for HARuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HARuntimeError::Graph(e) => write!(f, "graph cannot be executed: {}", e),
+            HARuntimeError::Runtime(e) => write!(f, "failed to start async runtime: {}", e),
+            HARuntimeError::NodePanicked(name) => write!(f, "node panicked: {}", name),
+            HARuntimeError::InvalidControl(msg) => write!(f, "invalid control message: {}", msg),
+        }
+    }
+}
+
+impl From<HAGraphError> for HARuntimeError {
+    fn from(e: HAGraphError) -> Self {
+        HARuntimeError::Graph(e)
+    }
+}
+
+impl Error for HARuntimeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HARuntimeError::Graph(e) => Some(e),
+            HARuntimeError::Runtime(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum NodeStatus {
+    Completed(AgentOutput),
+    Failed(HAAgentError),
+    // The node did not run (or its result was discarded): none of its inbound edges fired
+    // because every parent failed, was skipped or had its condition reject the output, or a
+    // `Control::SkipNode` covered it.
+    Skipped,
+}
+
+#[derive(Debug, Default)]
+pub struct RunReport {
+    pub results: HashMap<NodeId, NodeStatus>,
+    // Outputs of completed sink nodes (nodes without successors), in layer order.
+    pub outputs: Vec<(NodeId, AgentOutput)>,
+    // How many times each executed node was called, retries and loop iterations included.
+    pub attempts: HashMap<NodeId, u32>,
+    // How many times the body of each loop ran to its back-edge, indexed by `LoopId`.
+    pub loop_iterations: Vec<usize>,
+}
+
+impl RunReport {
+    pub fn status(&self, node: NodeId) -> Option<&NodeStatus> {
+        self.results.get(&node)
+    }
+
+    pub fn output(&self, node: NodeId) -> Option<&AgentOutput> {
+        match self.results.get(&node) {
+            Some(NodeStatus::Completed(output)) => Some(output),
+            _ => None,
+        }
+    }
+
+    pub fn iterations(&self, id: LoopId) -> usize {
+        self.loop_iterations.get(id.0).copied().unwrap_or(0)
+    }
+
+    pub fn is_success(&self) -> bool {
+        !self
+            .results
+            .values()
+            .any(|status| matches!(status, NodeStatus::Failed(_)))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32, initial_backoff: Duration) -> Self {
+        Self {
+            max_retries,
+            initial_backoff,
+            max_backoff: initial_backoff.saturating_mul(16),
+        }
+    }
+
+    pub fn none() -> Self {
+        Self::new(0, Duration::ZERO)
+    }
+
+    // Exponential backoff: initial_backoff * 2^retry, capped at max_backoff.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+// Decides what happens after a node call finishes. The returned `Control` drives the runner:
+// `RetryNode` re-runs the node (bounded by the `RetryPolicy`), `SkipNode` marks a node and its
+// descendants as skipped, and `Continue` records the result as is. A node can only skip itself
+// or a node that has not run yet.
+pub type Controller =
+    Arc<dyn Fn(NodeId, &Result<AgentOutput, HAAgentError>) -> Control + Send + Sync>;
+
+// Retries failed nodes and continues otherwise.
+pub fn retry_on_failure() -> Controller {
+    Arc::new(|node, result| match result {
+        Ok(_) => Control::Continue,
+        Err(_) => Control::RetryNode(node),
+    })
+}
+
+pub struct GraphRunner {
+    graph: Graph,
+    preds: Vec<Vec<NodeId>>,
+    layer_of: Vec<usize>,
+    max_concurrency: usize,
+    retry: RetryPolicy,
+    controller: Controller,
+}
+
+struct NodeRun {
+    node: NodeId,
+    result: Result<AgentOutput, HAAgentError>,
+    control: Control,
+    attempts: u32,
+    emitted: Vec<MessagePayload>,
+}
+
+impl GraphRunner {
+    pub fn new(graph: Graph) -> Result<Self, HARuntimeError> {
+        let n = graph.nodes.len();
+        if graph.out.len() != n {
+            return Err(HAGraphError::InvalidGraph("length mismatch".to_string()).into());
+        }
+
+        let mut layer_of = vec![usize::MAX; n];
+        for (i, layer) in graph.layers.iter().enumerate() {
+            for &node in layer {
+                if node.0 >= n || layer_of[node.0] != usize::MAX {
+                    return Err(HAGraphError::InvalidGraph(format!(
+                        "node {} is missing or scheduled twice in layers",
+                        node.0
+                    ))
+                    .into());
+                }
+                layer_of[node.0] = i;
+            }
+        }
+        if let Some(missing) = layer_of.iter().position(|&i| i == usize::MAX) {
+            return Err(HAGraphError::InvalidGraph(format!(
+                "node {missing} is not scheduled in any layer"
+            ))
+            .into());
+        }
+        for edge in &graph.loops {
+            let in_range = |node: NodeId| node.0 < n;
+            if !in_range(edge.from) || !in_range(edge.to) || !edge.body.iter().all(|&b| in_range(b))
+            {
+                return Err(HAGraphError::InvalidGraph(format!(
+                    "loop from={} to={} points to a missing node",
+                    edge.from.0, edge.to.0
+                ))
+                .into());
+            }
+        }
+
+        let mut preds = vec![Vec::new(); n];
+        for (from, targets) in graph.out.iter().enumerate() {
+            for &to in targets {
+                if to.0 >= n {
+                    return Err(HAGraphError::InvalidGraph(format!(
+                        "edge points to missing node index {}",
+                        to.0
+                    ))
+                    .into());
+                }
+                preds[to.0].push(NodeId(from));
+            }
+        }
+
+        let max_concurrency = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+
+        Ok(Self {
+            graph,
+            preds,
+            layer_of,
+            max_concurrency,
+            retry: RetryPolicy::none(),
+            controller: retry_on_failure(),
+        })
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_controller(mut self, controller: Controller) -> Self {
+        self.controller = controller;
+        self
+    }
+
+    // Upper bound on how many nodes of the same layer run at once. Values below 1 are treated as 1.
+    pub fn with_max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency.max(1);
+        self
+    }
+
+    pub fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    pub fn max_concurrency(&self) -> usize {
+        self.max_concurrency
+    }
+
+    // Blocking entry point: drives `run_async` on a private tokio runtime.
+    // Blocking agents run on tokio's blocking pool, async agents on the runtime itself.
+    // Must not be called from inside another tokio runtime; use `run_async` there.
+    pub fn run(&self, input: AgentInput) -> Result<RunReport, HARuntimeError> {
+        let mut cx = scratch_context();
+        self.run_with_context(input, &mut cx)
+    }
+
+    // Like `run`, but agents see `cx` and every node's output is appended to it.
+    pub fn run_with_context(
+        &self,
+        input: AgentInput,
+        cx: &mut AgentContext,
+    ) -> Result<RunReport, HARuntimeError> {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(HARuntimeError::Runtime)?;
+        rt.block_on(self.run_async_with_context(input, cx))
+    }
+
+    pub async fn run_async(&self, input: AgentInput) -> Result<RunReport, HARuntimeError> {
+        let mut cx = scratch_context();
+        self.run_async_with_context(input, &mut cx).await
+    }
+
+    // Runs every layer in order. Nodes within a layer are independent, so they run concurrently
+    // (bounded by `max_concurrency`) and are joined before the next layer starts. Root nodes
+    // receive `input`; every other node receives the outputs carried by its inbound edges that
+    // fired. A failed node does not stop the run; a node is skipped once none of its parents
+    // completed, so a fan-in node still runs with the outputs of the parents that did.
+    // After every call the controller decides whether to retry, skip or continue. When a loop
+    // source completes and the loop does not exit, the runner jumps back to the loop target's
+    // layer and re-runs the loop body with the source output as the target's input.
+    // Agents get a snapshot of `cx` taken when their layer starts. Once a layer is joined, every
+    // node's output (or error) is appended to `cx` in layer order as a message from
+    // `Sender::Node`, followed by the messages the node emitted.
+    pub async fn run_async_with_context(
+        &self,
+        input: AgentInput,
+        cx: &mut AgentContext,
+    ) -> Result<RunReport, HARuntimeError> {
+        let mut report = RunReport {
+            loop_iterations: vec![0; self.graph.loops.len()],
+            ..RunReport::default()
+        };
+        let permits = Arc::new(Semaphore::new(self.max_concurrency));
+        let mut feedback: HashMap<NodeId, AgentOutput> = HashMap::new();
+        let mut layer_idx = 0;
+        while let Some(layer) = self.graph.layers.get(layer_idx) {
+            let mut tasks = JoinSet::new();
+            let mut task_nodes = HashMap::new();
+            let mut statuses = HashMap::with_capacity(layer.len());
+            let mut emitted = HashMap::new();
+            let mut skips = Vec::new();
+            let history = Arc::new(cx.clone());
+            for &node in layer {
+                // Already settled: covered by an earlier `SkipNode`, or outside the body of a
+                // loop being repeated, where the result of the earlier iteration stands.
+                if report.results.contains_key(&node) {
+                    continue;
+                }
+                let node_input = match feedback.remove(&node) {
+                    Some(output) => Some(output.into()),
+                    None => self.node_input(node, &input, &report, &history),
+                };
+                let Some(node_input) = node_input else {
+                    statuses.insert(node, NodeStatus::Skipped);
+                    continue;
+                };
+                let worker = self.graph.nodes[node.0].worker.clone();
+                let controller = Arc::clone(&self.controller);
+                let retry = self.retry;
+                let permits = Arc::clone(&permits);
+                let call_cx = CallContext::new(node, Arc::clone(&history));
+                let handle = tasks.spawn(async move {
+                    // The semaphore is never closed, so acquiring cannot fail.
+                    let _permit = permits.acquire_owned().await.ok();
+                    run_node(node, worker, node_input, call_cx, controller, retry).await
+                });
+                task_nodes.insert(handle.id(), node);
+            }
+
+            while let Some(joined) = tasks.join_next_with_id().await {
+                let run = match joined {
+                    Ok((_, Ok(run))) => run,
+                    Ok((_, Err(node))) => return Err(self.panicked(node)),
+                    Err(e) => return Err(self.panicked(task_nodes[&e.id()])),
+                };
+                let status = match (run.result, &run.control) {
+                    (_, Control::SkipNode(target)) if *target == run.node => NodeStatus::Skipped,
+                    (Ok(output), _) => NodeStatus::Completed(output),
+                    (Err(e), _) => NodeStatus::Failed(e),
+                };
+                match run.control {
+                    Control::SkipNode(target) => skips.push(target),
+                    // Retries of the node itself are handled inside `run_node`; reaching this
+                    // point means the policy ran out of retries.
+                    Control::RetryNode(target) if target != run.node => {
+                        return Err(HARuntimeError::InvalidControl(format!(
+                            "node {} asked to retry node {}, only the node itself can be retried",
+                            run.node.0, target.0
+                        )));
+                    }
+                    _ => {}
+                }
+                *report.attempts.entry(run.node).or_insert(0) += run.attempts;
+                emitted.insert(run.node, run.emitted);
+                statuses.insert(run.node, status);
+            }
+
+            for &node in layer {
+                let Some(status) = statuses.remove(&node) else {
+                    continue;
+                };
+                if let NodeStatus::Completed(output) = &status
+                    && self.graph.out[node.0].is_empty()
+                {
+                    report.outputs.push((node, output.clone()));
+                }
+                let payload = match &status {
+                    NodeStatus::Completed(output) => Some(output.clone().into()),
+                    NodeStatus::Failed(e) => Some(MessagePayload::Error(e.to_string())),
+                    NodeStatus::Skipped => None,
+                };
+                let payloads = payload
+                    .into_iter()
+                    .chain(emitted.remove(&node).unwrap_or_default());
+                for payload in payloads {
+                    cx.push(AgentMessage::new(
+                        cx.run_id.clone(),
+                        Sender::Node(node),
+                        payload,
+                    ));
+                }
+                report.results.insert(node, status);
+            }
+            for target in skips {
+                self.skip_subtree(target, &mut report)?;
+            }
+
+            layer_idx = match self.repeat_loop(layer_idx, &mut report, &mut feedback) {
+                Some(target_layer) => target_layer,
+                None => layer_idx + 1,
+            };
+        }
+        Ok(report)
+    }
+
+    // Checks the loops whose source sits in `layer_idx`. The first loop that neither exits nor
+    // runs out of iterations has its body cleared from the report and its target fed with the
+    // source output; the layer to resume from is returned. Nodes that depend on the loop body
+    // but are not part of it keep the result of the iteration they saw.
+    fn repeat_loop(
+        &self,
+        layer_idx: usize,
+        report: &mut RunReport,
+        feedback: &mut HashMap<NodeId, AgentOutput>,
+    ) -> Option<usize> {
+        for (i, edge) in self.graph.loops.iter().enumerate() {
+            if self.layer_of[edge.from.0] != layer_idx {
+                continue;
+            }
+            let Some(output) = report.output(edge.from) else {
+                continue;
+            };
+            let exit = (edge.exit)(output);
+            let output = output.clone();
+            report.loop_iterations[i] += 1;
+            if exit || report.loop_iterations[i] >= edge.max_iterations {
+                continue;
+            }
+
+            feedback.insert(edge.to, output);
+            for node in &edge.body {
+                report.results.remove(node);
+            }
+            report.outputs.retain(|(node, _)| !edge.body.contains(node));
+            return Some(self.layer_of[edge.to.0]);
+        }
+        None
+    }
+
+    fn panicked(&self, node: NodeId) -> HARuntimeError {
+        HARuntimeError::NodePanicked(self.graph.nodes[node.0].name.clone())
+    }
+
+    // Marks `target` and every descendant that has not run yet as skipped. A target that
+    // already completed or failed, e.g. a sibling in the same layer, is rejected: its result
+    // has been handed on, so its descendants cannot be skipped consistently.
+    fn skip_subtree(&self, target: NodeId, report: &mut RunReport) -> Result<(), HARuntimeError> {
+        if target.0 >= self.graph.nodes.len() {
+            return Err(HARuntimeError::InvalidControl(format!(
+                "cannot skip missing node {}",
+                target.0
+            )));
+        }
+        if let Some(NodeStatus::Completed(_) | NodeStatus::Failed(_)) = report.results.get(&target)
+        {
+            return Err(HARuntimeError::InvalidControl(format!(
+                "cannot skip node {}, it already ran",
+                target.0
+            )));
+        }
+        let mut stack = vec![target];
+        while let Some(node) = stack.pop() {
+            if node != target && report.results.contains_key(&node) {
+                continue;
+            }
+            report.results.entry(node).or_insert(NodeStatus::Skipped);
+            stack.extend(self.graph.out[node.0].iter().copied());
+        }
+        Ok(())
+    }
+
+    // Collects the outputs of the inbound edges that fire. An edge fires when its source
+    // completed and its condition, if any, accepts the output and `history`. Returns `None` when
+    // nothing fired.
+    // A node with a single predecessor receives that output as text; a fan-in node (several
+    // predecessors) always receives `AgentInput::Combined`, even if only one edge fired.
+    fn node_input(
+        &self,
+        node: NodeId,
+        input: &AgentInput,
+        report: &RunReport,
+        history: &AgentContext,
+    ) -> Option<AgentInput> {
+        let preds = &self.preds[node.0];
+        if preds.is_empty() {
+            return Some(input.clone());
+        }
+
+        let inputs: Vec<UpstreamOutput> = preds
+            .iter()
+            .filter_map(|&pred| {
+                let output = report.output(pred)?;
+                match self.graph.conditions.get(&(pred, node)) {
+                    Some(predicate) if !predicate(output, history) => None,
+                    _ => Some(UpstreamOutput {
+                        node: pred,
+                        name: self.graph.nodes[pred.0].name.clone(),
+                        output: output.clone(),
+                    }),
+                }
+            })
+            .collect();
+        if inputs.is_empty() {
+            return None;
+        }
+        if preds.len() == 1 {
+            return inputs
+                .into_iter()
+                .next()
+                .map(|upstream| upstream.output.into());
+        }
+        Some(AgentInput::Combined(CombinedInput { inputs }))
+    }
+}
+
+// Context for `run` and `run_async` when the caller keeps no history of its own.
+fn scratch_context() -> AgentContext {
+    AgentContext::new(RunId(0), UserId(0), usize::MAX)
+}
+
+// Calls the node until the controller stops asking for a retry or the retry budget is spent.
+// A `Control` emitted by the agent applies when the controller answers `Continue`. Only the
+// messages emitted by the last attempt are kept. Returns the node id as the error when the
+// agent panicked.
+async fn run_node(
+    node: NodeId,
+    worker: Worker,
+    input: AgentInput,
+    cx: CallContext,
+    controller: Controller,
+    retry: RetryPolicy,
+) -> Result<NodeRun, NodeId> {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let (result, mut attempt) = call_worker(&worker, input.clone(), cx.fork())
+            .await
+            .map_err(|_| node)?;
+        let control = match (controller(node, &result), attempt.control()) {
+            (Control::Continue, Some(emitted)) => emitted.clone(),
+            (control, _) => control,
+        };
+        match control {
+            Control::RetryNode(target) if target == node && attempts <= retry.max_retries => {
+                tokio::time::sleep(retry.backoff(attempts - 1)).await;
+            }
+            control => {
+                return Ok(NodeRun {
+                    node,
+                    result,
+                    control,
+                    attempts,
+                    emitted: attempt.take_emitted(),
+                });
+            }
+        }
+    }
+}
+
+async fn call_worker(
+    worker: &Worker,
+    input: AgentInput,
+    mut cx: CallContext,
+) -> Result<(Result<AgentOutput, HAAgentError>, CallContext), JoinError> {
+    match worker {
+        Worker::Blocking(agent) => {
+            let agent = Arc::clone(agent);
+            tokio::task::spawn_blocking(move || {
+                let result = agent.call_with_context(input, &mut cx);
+                (result, cx)
+            })
+            .await
+        }
+        Worker::Async(agent) => {
+            let result = agent.call_with_context(input, &mut cx).await;
+            Ok((result, cx))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        agent::{Agent, AgentFuture, AsyncAgent},
+        context::blob::Blob,
+        graph::GraphBuilder,
+    };
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    // Appends its id to the incoming text; fan-in inputs are joined line by line first.
+    struct AppendAgent(&'static str);
+
+    impl Agent for AppendAgent {
+        fn id(&self) -> &str {
+            self.0
+        }
+
+        fn call(&self, agent_input: AgentInput) -> Result<AgentOutput, HAAgentError> {
+            match agent_input {
+                AgentInput::Text(text) => Ok(AgentOutput::FinalAnswer(format!("{text}{}", self.0))),
+                AgentInput::Combined(combined) => Ok(AgentOutput::FinalAnswer(format!(
+                    "{}{}",
+                    combined.to_text(),
+                    self.0
+                ))),
+                _ => Err(HAAgentError::InvalidInput("expected text input".into())),
+            }
+        }
+    }
+
+    fn agent(id: &'static str) -> Arc<dyn Agent + Send + Sync> {
+        Arc::new(AppendAgent(id))
+    }
+
+    fn text(report: &RunReport, node: NodeId) -> &str {
+        report.output(node).expect("node should complete").text()
+    }
+
+    #[test]
+    fn run_chain_routes_outputs() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("A", agent("a"));
+        let c = b.add_node("C", agent("c"));
+        b.add_edge(a, c).unwrap();
+
+        let runner = GraphRunner::new(b.build().unwrap()).unwrap();
+        let report = runner.run(AgentInput::Text(">".into())).unwrap();
+
+        assert!(report.is_success());
+        assert_eq!(text(&report, a), ">a");
+        assert_eq!(text(&report, c), ">ac");
+        assert_eq!(report.outputs.len(), 1);
+        assert_eq!(report.outputs[0].0, c);
+    }
+
+    // Records the fan-in input it received.
+    struct CaptureAgent(std::sync::Mutex<Option<CombinedInput>>);
+
+    impl Agent for CaptureAgent {
+        fn id(&self) -> &str {
+            "capture"
+        }
+
+        fn call(&self, agent_input: AgentInput) -> Result<AgentOutput, HAAgentError> {
+            match agent_input {
+                AgentInput::Combined(combined) => {
+                    *self.0.lock().unwrap() = Some(combined);
+                    Ok(AgentOutput::FinalAnswer(String::new()))
+                }
+                _ => Err(HAAgentError::InvalidInput("expected combined input".into())),
+            }
+        }
+    }
+
+    #[test]
+    fn run_fan_in_receives_every_upstream_output() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("capture", agent(""));
+        let listen = b.add_node("listen", agent("heard"));
+        let vision = b.add_node("vision", agent("saw"));
+        let capture = Arc::new(CaptureAgent(std::sync::Mutex::new(None)));
+        let answer = b.add_node("answer", capture.clone());
+        b.add_edge(a, listen).unwrap();
+        b.add_edge(a, vision).unwrap();
+        b.add_edge(listen, answer).unwrap();
+        b.add_edge(vision, answer).unwrap();
+
+        let runner = GraphRunner::new(b.build().unwrap()).unwrap();
+        let report = runner.run(AgentInput::Text(">".into())).unwrap();
+        assert!(report.is_success());
+
+        let combined = capture.0.lock().unwrap().take().unwrap();
+        assert_eq!(combined.len(), 2);
+        assert_eq!(combined.get("listen").unwrap().text(), ">heard");
+        assert_eq!(combined.by_node(vision).unwrap().text(), ">saw");
+        assert_eq!(combined.inputs[0].node, listen);
+    }
+
+    #[test]
+    fn run_branch_and_merge_joins_inputs() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("A", agent("a"));
+        let b1 = b.add_node("B", agent("b"));
+        let c = b.add_node("C", agent("c"));
+        let d = b.add_node("D", agent("d"));
+        b.add_edge(a, b1).unwrap();
+        b.add_edge(a, c).unwrap();
+        b.add_edge(b1, d).unwrap();
+        b.add_edge(c, d).unwrap();
+
+        let runner = GraphRunner::new(b.build().unwrap()).unwrap();
+        let report = runner.run(AgentInput::Text(">".into())).unwrap();
+
+        assert_eq!(text(&report, d), ">ab\n>acd");
+    }
+
+    #[test]
+    fn run_skips_descendants_of_failed_node() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("A", agent("a"));
+        let c = b.add_node("C", agent("c"));
+        let d = b.add_node("D", agent("d"));
+        b.add_edge(a, c).unwrap();
+
+        let runner = GraphRunner::new(b.build().unwrap()).unwrap();
+        let report = runner
+            .run(AgentInput::Audio(Blob::new(vec![0, 1])))
+            .unwrap();
+
+        assert!(!report.is_success());
+        assert!(matches!(report.status(a), Some(NodeStatus::Failed(_))));
+        assert!(matches!(report.status(c), Some(NodeStatus::Skipped)));
+        assert!(matches!(report.status(d), Some(NodeStatus::Failed(_))));
+        assert!(report.outputs.is_empty());
+    }
+
+    // Records the highest number of agents running at the same time.
+    struct ConcurrencyProbe {
+        running: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    struct SlowAgent(Arc<ConcurrencyProbe>);
+
+    impl Agent for SlowAgent {
+        fn id(&self) -> &str {
+            "slow"
+        }
+
+        fn call(&self, _agent_input: AgentInput) -> Result<AgentOutput, HAAgentError> {
+            let running = self.0.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.0.peak.fetch_max(running, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            self.0.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(AgentOutput::FinalAnswer(String::new()))
+        }
+    }
+
+    fn peak_concurrency(max_concurrency: usize) -> usize {
+        let probe = Arc::new(ConcurrencyProbe {
+            running: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        });
+        let mut b = GraphBuilder::new();
+        for name in ["A", "B", "C"] {
+            b.add_node(name, Arc::new(SlowAgent(Arc::clone(&probe))));
+        }
+
+        let runner = GraphRunner::new(b.build().unwrap())
+            .unwrap()
+            .with_max_concurrency(max_concurrency);
+        let report = runner.run(AgentInput::Text(String::new())).unwrap();
+
+        assert!(report.is_success());
+        assert_eq!(report.outputs.len(), 3);
+        probe.peak.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn run_executes_layer_nodes_concurrently() {
+        assert_eq!(peak_concurrency(3), 3);
+    }
+
+    #[test]
+    fn run_respects_max_concurrency() {
+        assert_eq!(peak_concurrency(1), 1);
+    }
+
+    struct AsyncAppendAgent(&'static str);
+
+    impl AsyncAgent for AsyncAppendAgent {
+        fn id(&self) -> &str {
+            self.0
+        }
+
+        fn call(&self, agent_input: AgentInput) -> AgentFuture<'_> {
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                AppendAgent(self.0).call(agent_input)
+            })
+        }
+    }
+
+    #[test]
+    fn run_mixes_blocking_and_async_nodes() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("A", agent("a"));
+        let c = b.add_async_node("C", Arc::new(AsyncAppendAgent("c")));
+        let d = b.add_node("D", agent("d"));
+        b.add_edge(a, c).unwrap();
+        b.add_edge(c, d).unwrap();
+
+        let runner = GraphRunner::new(b.build().unwrap()).unwrap();
+        let report = runner.run(AgentInput::Text(">".into())).unwrap();
+
+        assert_eq!(text(&report, d), ">acd");
+    }
+
+    // Fails until it has been called `failures + 1` times.
+    struct FlakyAgent {
+        failures: usize,
+        calls: AtomicUsize,
+    }
+
+    impl Agent for FlakyAgent {
+        fn id(&self) -> &str {
+            "flaky"
+        }
+
+        fn call(&self, _agent_input: AgentInput) -> Result<AgentOutput, HAAgentError> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                Err(HAAgentError::InvalidInput("not yet".into()))
+            } else {
+                Ok(AgentOutput::FinalAnswer("ok".into()))
+            }
+        }
+    }
+
+    fn flaky_report(failures: usize, retry: RetryPolicy) -> (NodeId, RunReport) {
+        let mut b = GraphBuilder::new();
+        let flaky = b.add_node(
+            "F",
+            Arc::new(FlakyAgent {
+                failures,
+                calls: AtomicUsize::new(0),
+            }),
+        );
+        let runner = GraphRunner::new(b.build().unwrap())
+            .unwrap()
+            .with_retry_policy(retry);
+        (flaky, runner.run(AgentInput::Text(String::new())).unwrap())
+    }
+
+    #[test]
+    fn run_retries_failed_node_with_backoff() {
+        let (flaky, report) = flaky_report(2, RetryPolicy::new(3, Duration::from_millis(1)));
+
+        assert_eq!(text(&report, flaky), "ok");
+        assert_eq!(report.attempts[&flaky], 3);
+    }
+
+    #[test]
+    fn run_stops_retrying_when_budget_is_spent() {
+        let (flaky, report) = flaky_report(5, RetryPolicy::new(2, Duration::from_millis(1)));
+
+        assert!(matches!(report.status(flaky), Some(NodeStatus::Failed(_))));
+        assert_eq!(report.attempts[&flaky], 3);
+    }
+
+    #[test]
+    fn retry_backoff_doubles_up_to_the_cap() {
+        let retry = RetryPolicy::new(5, Duration::from_millis(10));
+
+        assert_eq!(retry.backoff(0), Duration::from_millis(10));
+        assert_eq!(retry.backoff(2), Duration::from_millis(40));
+        assert_eq!(retry.backoff(10), Duration::from_millis(160));
+    }
+
+    #[test]
+    fn run_skip_node_marks_subtree_skipped() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("A", agent("a"));
+        let b1 = b.add_node("B", agent("b"));
+        let c = b.add_node("C", agent("c"));
+        let d = b.add_node("D", agent("d"));
+        let e = b.add_node("E", agent("e"));
+        b.add_edge(a, b1).unwrap();
+        b.add_edge(a, c).unwrap();
+        b.add_edge(b1, d).unwrap();
+        b.add_edge(c, e).unwrap();
+
+        let runner = GraphRunner::new(b.build().unwrap())
+            .unwrap()
+            .with_controller(Arc::new(move |node, _| {
+                if node == a {
+                    Control::SkipNode(b1)
+                } else {
+                    Control::Continue
+                }
+            }));
+        let report = runner.run(AgentInput::Text(">".into())).unwrap();
+
+        assert!(report.is_success());
+        assert!(matches!(report.status(b1), Some(NodeStatus::Skipped)));
+        assert!(matches!(report.status(d), Some(NodeStatus::Skipped)));
+        assert!(!report.attempts.contains_key(&b1));
+        assert_eq!(text(&report, e), ">ace");
+    }
+
+    #[test]
+    fn run_rejects_skip_of_a_node_that_already_ran() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("A", agent("a"));
+        let b1 = b.add_node("B", agent("b"));
+        let c = b.add_node("C", agent("c"));
+        let d = b.add_node("D", agent("d"));
+        b.add_edge(a, b1).unwrap();
+        b.add_edge(a, c).unwrap();
+        b.add_edge(c, d).unwrap();
+
+        // B and C share a layer, so C has completed by the time B's skip is applied.
+        let runner = GraphRunner::new(b.build().unwrap())
+            .unwrap()
+            .with_controller(Arc::new(move |node, _| {
+                if node == b1 {
+                    Control::SkipNode(c)
+                } else {
+                    Control::Continue
+                }
+            }));
+
+        let err = runner.run(AgentInput::Text(String::new())).unwrap_err();
+        assert!(matches!(err, HARuntimeError::InvalidControl(_)));
+    }
+
+    #[test]
+    fn run_skip_node_on_self_discards_failure() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("A", agent("a"));
+        let c = b.add_node("C", agent("c"));
+        b.add_edge(a, c).unwrap();
+
+        let runner = GraphRunner::new(b.build().unwrap())
+            .unwrap()
+            .with_controller(Arc::new(|node, result| match result {
+                Ok(_) => Control::Continue,
+                Err(_) => Control::SkipNode(node),
+            }));
+        let report = runner.run(AgentInput::Audio(Blob::new(vec![]))).unwrap();
+
+        assert!(report.is_success());
+        assert!(matches!(report.status(a), Some(NodeStatus::Skipped)));
+        assert!(matches!(report.status(c), Some(NodeStatus::Skipped)));
+    }
+
+    #[test]
+    fn run_rejects_retry_of_another_node() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("A", agent("a"));
+        let c = b.add_node("C", agent("c"));
+
+        let runner = GraphRunner::new(b.build().unwrap())
+            .unwrap()
+            .with_controller(Arc::new(move |node, _| {
+                if node == a {
+                    Control::RetryNode(c)
+                } else {
+                    Control::Continue
+                }
+            }));
+
+        let err = runner.run(AgentInput::Text(String::new())).unwrap_err();
+        assert!(matches!(err, HARuntimeError::InvalidControl(_)));
+    }
+
+    #[test]
+    fn run_follows_only_firing_conditional_edges() {
+        let mut b = GraphBuilder::new();
+        let listen = b.add_node("listen", agent("?"));
+        let vision = b.add_node("vision", agent("v"));
+        let chat = b.add_node("chat", agent("c"));
+        let answer = b.add_node("answer", agent("!"));
+        b.add_conditional_edge(listen, vision, |output, _| {
+            output.text().contains("what am I looking at")
+        })
+        .unwrap();
+        b.add_conditional_edge(listen, chat, |output, _| {
+            !output.text().contains("what am I looking at")
+        })
+        .unwrap();
+        b.add_edge(vision, answer).unwrap();
+        b.add_edge(chat, answer).unwrap();
+
+        let runner = GraphRunner::new(b.build().unwrap()).unwrap();
+        let report = runner
+            .run(AgentInput::Text("what am I looking at".into()))
+            .unwrap();
+
+        assert!(report.is_success());
+        assert!(matches!(report.status(chat), Some(NodeStatus::Skipped)));
+        assert_eq!(text(&report, answer), "what am I looking at?v!");
+    }
+
+    #[test]
+    fn run_conditional_edge_reads_context() {
+        let mut b = GraphBuilder::new();
+        let listen = b.add_node("listen", agent("?"));
+        let vision = b.add_node("vision", agent("v"));
+        b.add_conditional_edge(listen, vision, |_, cx| {
+            cx.iter().any(|msg| msg.text() == Some("camera on"))
+        })
+        .unwrap();
+        let runner = GraphRunner::new(b.build().unwrap()).unwrap();
+
+        let mut cx = AgentContext::new(RunId(1), UserId(1), 8);
+        cx.push(AgentMessage::new(
+            RunId(1),
+            Sender::User,
+            MessagePayload::Text("camera on".into()),
+        ));
+        let report = runner
+            .run_with_context(AgentInput::Text(">".into()), &mut cx)
+            .unwrap();
+        assert_eq!(text(&report, vision), ">?v");
+
+        let report = runner.run(AgentInput::Text(">".into())).unwrap();
+        assert!(matches!(report.status(vision), Some(NodeStatus::Skipped)));
+    }
+
+    #[test]
+    fn run_skips_node_when_no_inbound_edge_fires() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("A", agent("a"));
+        let c = b.add_node("C", agent("c"));
+        let d = b.add_node("D", agent("d"));
+        b.add_conditional_edge(a, c, |_, _| false).unwrap();
+        b.add_edge(c, d).unwrap();
+
+        let runner = GraphRunner::new(b.build().unwrap()).unwrap();
+        let report = runner.run(AgentInput::Text(">".into())).unwrap();
+
+        assert!(matches!(report.status(c), Some(NodeStatus::Skipped)));
+        assert!(matches!(report.status(d), Some(NodeStatus::Skipped)));
+        assert!(!report.attempts.contains_key(&c));
+        assert!(report.outputs.is_empty());
+    }
+
+    #[test]
+    fn run_fan_in_runs_with_parents_that_completed() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node(
+            "A",
+            Arc::new(FlakyAgent {
+                failures: usize::MAX,
+                calls: AtomicUsize::new(0),
+            }),
+        );
+        let b1 = b.add_node("B", agent("b"));
+        let c = b.add_node("C", agent("c"));
+        let d = b.add_node("D", agent("d"));
+        b.add_edge(a, c).unwrap();
+        b.add_edge(b1, d).unwrap();
+        b.add_edge(c, d).unwrap();
+
+        let runner = GraphRunner::new(b.build().unwrap()).unwrap();
+        let report = runner.run(AgentInput::Text(">".into())).unwrap();
+
+        assert!(matches!(report.status(a), Some(NodeStatus::Failed(_))));
+        assert_eq!(text(&report, b1), ">b");
+        assert!(matches!(report.status(c), Some(NodeStatus::Skipped)));
+        // B completed, so D still runs with B's output only.
+        assert_eq!(text(&report, d), ">bd");
+    }
+
+    // Counts how often it ran and appends the count to the incoming text.
+    struct CountingAgent(AtomicUsize);
+
+    impl Agent for CountingAgent {
+        fn id(&self) -> &str {
+            "count"
+        }
+
+        fn call(&self, agent_input: AgentInput) -> Result<AgentOutput, HAAgentError> {
+            let n = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            match agent_input {
+                AgentInput::Text(text) => Ok(AgentOutput::FinalAnswer(format!("{text}{n}"))),
+                _ => Err(HAAgentError::InvalidInput("expected text input".into())),
+            }
+        }
+    }
+
+    #[test]
+    fn run_repeats_loop_body_until_exit() {
+        let mut b = GraphBuilder::new();
+        let ask = b.add_node("ask", Arc::new(CountingAgent(AtomicUsize::new(0))));
+        let tool = b.add_node("tool", agent("t"));
+        let answer = b.add_node("answer", agent("!"));
+        b.add_edge(ask, tool).unwrap();
+        b.add_edge(tool, answer).unwrap();
+        let id = b
+            .add_loop(tool, ask, 10, |output| output.text().ends_with("3t"))
+            .unwrap();
+
+        let runner = GraphRunner::new(b.build().unwrap()).unwrap();
+        let report = runner.run(AgentInput::Text(">".into())).unwrap();
+
+        assert!(report.is_success());
+        assert_eq!(report.iterations(id), 3);
+        assert_eq!(report.attempts[&ask], 3);
+        assert_eq!(report.attempts[&answer], 1);
+        assert_eq!(text(&report, answer), ">1t2t3t!");
+    }
+
+    #[test]
+    fn run_stops_loop_at_max_iterations() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("A", agent("a"));
+        let id = b.add_loop(a, a, 4, |_| false).unwrap();
+
+        let runner = GraphRunner::new(b.build().unwrap()).unwrap();
+        let report = runner.run(AgentInput::Text(">".into())).unwrap();
+
+        assert_eq!(report.iterations(id), 4);
+        assert_eq!(report.outputs.len(), 1);
+        assert_eq!(text(&report, a), ">aaaa");
+    }
+
+    // Answers with the texts it finds in the history and can emit a control message.
+    struct HistoryAgent(Option<Control>);
+
+    impl Agent for HistoryAgent {
+        fn id(&self) -> &str {
+            "history"
+        }
+
+        fn call(&self, _agent_input: AgentInput) -> Result<AgentOutput, HAAgentError> {
+            Err(HAAgentError::InvalidInput("needs a context".into()))
+        }
+
+        fn call_with_context(
+            &self,
+            _agent_input: AgentInput,
+            cx: &mut CallContext,
+        ) -> Result<AgentOutput, HAAgentError> {
+            if let Some(control) = &self.0 {
+                cx.emit_control(control.clone());
+            }
+            let seen = cx.history().query().texts().join("|");
+            Ok(AgentOutput::FinalAnswer(seen))
+        }
+    }
+
+    #[test]
+    fn run_with_context_appends_node_outputs() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("A", agent("a"));
+        let failing = b.add_node(
+            "F",
+            Arc::new(FlakyAgent {
+                failures: usize::MAX,
+                calls: AtomicUsize::new(0),
+            }),
+        );
+        let history = b.add_node("H", Arc::new(HistoryAgent(None)));
+        b.add_edge(a, history).unwrap();
+        let runner = GraphRunner::new(b.build().unwrap()).unwrap();
+
+        let mut cx = AgentContext::new(RunId(7), UserId(1), 16);
+        cx.push(AgentMessage::new(
+            RunId(7),
+            Sender::User,
+            MessagePayload::Text("hi".into()),
+        ));
+        let report = runner
+            .run_with_context(AgentInput::Text(">".into()), &mut cx)
+            .unwrap();
+
+        // The history agent ran in the second layer and saw the first one.
+        assert_eq!(text(&report, history), "hi|>a|agent Input Error: not yet");
+        let senders: Vec<&Sender> = cx.iter().map(|msg| &msg.from).collect();
+        assert_eq!(
+            senders,
+            [
+                &Sender::User,
+                &Sender::Node(a),
+                &Sender::Node(failing),
+                &Sender::Node(history)
+            ]
+        );
+        assert!(matches!(cx.messages()[2].payload, MessagePayload::Error(_)));
+        assert!(cx.iter().all(|msg| msg.run == RunId(7)));
+    }
+
+    #[test]
+    fn run_applies_control_emitted_by_agent() {
+        let mut b = GraphBuilder::new();
+        let a = b.add_node("A", agent("a"));
+        let downstream = b.add_node("D", agent("d"));
+        let emitter = b.add_node(
+            "E",
+            Arc::new(HistoryAgent(Some(Control::SkipNode(downstream)))),
+        );
+        b.add_edge(a, emitter).unwrap();
+        b.add_edge(emitter, downstream).unwrap();
+        let runner = GraphRunner::new(b.build().unwrap()).unwrap();
+
+        let mut cx = AgentContext::new(RunId(1), UserId(1), 16);
+        let report = runner
+            .run_with_context(AgentInput::Text(">".into()), &mut cx)
+            .unwrap();
+
+        assert!(report.output(emitter).is_some());
+        assert!(matches!(
+            report.status(downstream),
+            Some(NodeStatus::Skipped)
+        ));
+        assert!(matches!(
+            cx.last().map(|msg| &msg.payload),
+            Some(MessagePayload::Control(Control::SkipNode(node))) if *node == downstream
+        ));
+    }
+
+    #[test]
+    fn new_rejects_unscheduled_node() {
+        let mut b = GraphBuilder::new();
+        b.add_node("A", agent("a"));
+        let mut graph = b.build().unwrap();
+        graph.layers.clear();
+
+        let err = GraphRunner::new(graph)
+            .err()
+            .expect("expected invalid graph");
+        assert!(matches!(
+            err,
+            HARuntimeError::Graph(HAGraphError::InvalidGraph(_))
+        ));
+    }
+}
@@ -0,0 +1,33 @@
+// Times `resample` on a minute of audio at the rates the glasses and common files use.
+// Run with `cargo bench -p hudagents-local`.
+use hudagents_local::audio::{WHISPER_SAMPLE_RATE, resample};
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+const RUNS: u32 = 5;
+
+fn time(from_rate: u32) -> Duration {
+    let samples: Vec<f32> = (0..from_rate as usize * 60)
+        .map(|i| (i as f32 * 0.0577).sin() * 0.3)
+        .collect();
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        black_box(resample(
+            black_box(&samples),
+            from_rate,
+            WHISPER_SAMPLE_RATE,
+        ));
+    }
+    start.elapsed() / RUNS
+}
+
+fn main() {
+    for from_rate in [48_000, 44_100, 22_050, 8_000] {
+        println!(
+            "resample 60 s {from_rate} Hz -> {WHISPER_SAMPLE_RATE} Hz: {:?}",
+            time(from_rate)
+        );
+    }
+}
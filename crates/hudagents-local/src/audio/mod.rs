@@ -0,0 +1,341 @@
+// PCM helpers for feeding whisper: downmixing, resampling and (with the `native-audio` feature)
+// in-process decoding.
+#[cfg(feature = "native-audio")]
+mod native;
+mod wav;
+
+#[cfg(feature = "native-audio")]
+pub use native::{decode, decode_for_whisper};
+pub use wav::{is_chunk_header, parse_wav};
+
+use std::{
+    borrow::Cow,
+    error::Error,
+    f64::consts::PI,
+    fmt::{self, Debug, Display},
+    sync::Arc,
+    time::Duration,
+};
+
+// Whisper expects 16 kHz mono f32 samples.
+pub const WHISPER_SAMPLE_RATE: u32 = 16_000;
+
+#[derive(Debug)]
+pub enum HAAudioError {
+    // The container or codec is not supported by the in-process decoder.
+    Unsupported(String),
+    DecodeFailed(String),
+}
+
+impl Display for HAAudioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HAAudioError::Unsupported(msg) => write!(f, "unsupported audio format: {}", msg),
+            HAAudioError::DecodeFailed(msg) => write!(f, "failed to decode audio: {}", msg),
+        }
+    }
+}
+
+impl Error for HAAudioError {}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DecodedAudio {
+    // Interleaved when `channels` > 1.
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub channels: usize,
+}
+
+impl DecodedAudio {
+    pub fn to_mono(&self) -> Vec<f32> {
+        to_mono(&self.samples, self.channels)
+    }
+
+    // Mono at `WHISPER_SAMPLE_RATE`.
+    pub fn to_whisper_pcm(&self) -> Vec<f32> {
+        resample(&self.to_mono(), self.sample_rate, WHISPER_SAMPLE_RATE)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PcmSamples {
+    I16(Arc<[i16]>),
+    F32(Arc<[f32]>),
+}
+
+// Raw interleaved PCM with a declared format, e.g. what the glasses firmware streams.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PcmAudio {
+    pub samples: PcmSamples,
+    pub sample_rate: u32,
+    pub channels: usize,
+}
+
+impl PcmAudio {
+    pub fn i16(samples: impl Into<Arc<[i16]>>, sample_rate: u32, channels: usize) -> Self {
+        Self {
+            samples: PcmSamples::I16(samples.into()),
+            sample_rate,
+            channels,
+        }
+    }
+
+    pub fn f32(samples: impl Into<Arc<[f32]>>, sample_rate: u32, channels: usize) -> Self {
+        Self {
+            samples: PcmSamples::F32(samples.into()),
+            sample_rate,
+            channels,
+        }
+    }
+
+    // Little-endian signed 16-bit samples; a trailing odd byte is ignored.
+    pub fn from_i16_le(bytes: &[u8], sample_rate: u32, channels: usize) -> Self {
+        let samples: Vec<i16> = bytes
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        Self::i16(samples, sample_rate, channels)
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.samples {
+            PcmSamples::I16(samples) => samples.len(),
+            PcmSamples::F32(samples) => samples.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn frames(&self) -> usize {
+        self.len() / self.channels.max(1)
+    }
+
+    pub fn duration(&self) -> Duration {
+        if self.sample_rate == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(self.frames() as f64 / f64::from(self.sample_rate))
+    }
+
+    // Mono at `WHISPER_SAMPLE_RATE`. 16 kHz mono f32 input is borrowed as is.
+    pub fn to_whisper_pcm(&self) -> Cow<'_, [f32]> {
+        let interleaved = match &self.samples {
+            PcmSamples::F32(samples)
+                if self.channels == 1 && self.sample_rate == WHISPER_SAMPLE_RATE =>
+            {
+                return Cow::Borrowed(samples);
+            }
+            PcmSamples::F32(samples) => Cow::Borrowed(&samples[..]),
+            PcmSamples::I16(samples) => Cow::Owned(
+                samples
+                    .iter()
+                    .map(|&s| f32::from(s) / 32_768.0)
+                    .collect::<Vec<_>>(),
+            ),
+        };
+        let mono = to_mono(&interleaved, self.channels);
+        Cow::Owned(resample(&mono, self.sample_rate, WHISPER_SAMPLE_RATE))
+    }
+}
+
+// Averages interleaved channels into one.
+pub fn to_mono(interleaved: &[f32], channels: usize) -> Vec<f32> {
+    if channels <= 1 {
+        return interleaved.to_vec();
+    }
+    interleaved
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+// Zero crossings of the sinc kernel on each side of the output sample.
+const SINC_ZERO_CROSSINGS: f64 = 16.0;
+
+// Above this many phases (rates with a large reduced ratio, e.g. 44_100 -> 16_001) the kernel is
+// computed per output sample instead of tabulated.
+const MAX_PHASES: u64 = 1_024;
+
+// Taps of the kernel for an output sample `frac` past input sample `n`; tap `k` weighs input
+// sample `n + first + k`.
+struct Kernel {
+    first: isize,
+    weights: Vec<f64>,
+}
+
+impl Kernel {
+    fn new(frac: f64, cutoff: f64, half_width: f64) -> Self {
+        let first = (frac - half_width).ceil() as isize;
+        let last = (frac + half_width).floor() as isize;
+        let weights = (first..=last)
+            .map(|k| {
+                let x = frac - k as f64;
+                cutoff * sinc(cutoff * x) * blackman(x / half_width)
+            })
+            .collect();
+        Self { first, weights }
+    }
+
+    fn apply(&self, samples: &[f32], n: usize) -> f32 {
+        let start = n as isize + self.first;
+        let mut acc = 0.0;
+        let mut weight_sum = 0.0;
+        for (k, weight) in self.weights.iter().enumerate() {
+            let j = start + k as isize;
+            if j < 0 {
+                continue;
+            }
+            let Some(&sample) = samples.get(j as usize) else {
+                break;
+            };
+            acc += f64::from(sample) * weight;
+            weight_sum += weight;
+        }
+        // Normalising keeps unit gain at DC, also where the kernel is cut off at the edges.
+        if weight_sum.abs() > f64::EPSILON {
+            (acc / weight_sum) as f32
+        } else {
+            0.0
+        }
+    }
+}
+
+// Band-limited resampling with a Blackman-windowed sinc kernel. When downsampling the kernel's
+// cutoff moves below the new Nyquist frequency, so content above it is filtered out instead of
+// aliasing into the speech band. Output sample `i` sits at input position `i * down / up`, so
+// only `up` distinct kernels exist; they are computed once (polyphase).
+pub fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate || samples.is_empty() || from_rate == 0 || to_rate == 0 {
+        return samples.to_vec();
+    }
+    let ratio = f64::from(to_rate) / f64::from(from_rate);
+    // Slightly below Nyquist leaves room for the window's transition band.
+    let cutoff = ratio.min(1.0) * 0.95;
+    let half_width = SINC_ZERO_CROSSINGS / cutoff;
+    let out_len = (samples.len() as f64 * ratio).round() as usize;
+    let divisor = gcd(u64::from(from_rate), u64::from(to_rate));
+    let (up, down) = (u64::from(to_rate) / divisor, u64::from(from_rate) / divisor);
+
+    let phases: Vec<Kernel> = if up <= MAX_PHASES {
+        (0..up)
+            .map(|phase| Kernel::new(phase as f64 / up as f64, cutoff, half_width))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    (0..out_len as u64)
+        .map(|i| {
+            let position = i * down;
+            let (n, phase) = ((position / up) as usize, position % up);
+            match phases.get(phase as usize) {
+                Some(kernel) => kernel.apply(samples, n),
+                None => Kernel::new(phase as f64 / up as f64, cutoff, half_width).apply(samples, n),
+            }
+        })
+        .collect()
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+// Blackman window over [-1, 1].
+fn blackman(x: f64) -> f64 {
+    if x.abs() > 1.0 {
+        return 0.0;
+    }
+    let t = (x + 1.0) / 2.0;
+    0.42 - 0.5 * (2.0 * PI * t).cos() + 0.08 * (4.0 * PI * t).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f64, rate: u32, secs: f64) -> Vec<f32> {
+        let len = (f64::from(rate) * secs) as usize;
+        (0..len)
+            .map(|i| (2.0 * PI * freq * i as f64 / f64::from(rate)).sin() as f32)
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn pcm_converts_to_whisper_format() {
+        let f32_pcm = PcmAudio::f32(vec![0.1, 0.2], WHISPER_SAMPLE_RATE, 1);
+        assert!(matches!(f32_pcm.to_whisper_pcm(), Cow::Borrowed(_)));
+
+        let i16_pcm = PcmAudio::from_i16_le(&[0x00, 0x40, 0x00, 0xc0], WHISPER_SAMPLE_RATE, 2);
+        assert_eq!(i16_pcm.frames(), 1);
+        assert_eq!(i16_pcm.to_whisper_pcm().as_ref(), &[0.0]);
+
+        let stereo_48k = PcmAudio::i16(vec![0i16; 9_600], 48_000, 2);
+        assert_eq!(stereo_48k.duration(), Duration::from_millis(100));
+        assert_eq!(stereo_48k.to_whisper_pcm().len(), 1_600);
+    }
+
+    #[test]
+    fn to_mono_averages_channels() {
+        assert_eq!(to_mono(&[1.0, 0.0, 0.5, 0.5], 2), vec![0.5, 0.5]);
+        assert_eq!(to_mono(&[0.25], 1), vec![0.25]);
+    }
+
+    #[test]
+    fn resample_keeps_speech_band_tone() {
+        let out = resample(&sine(440.0, 48_000, 1.0), 48_000, WHISPER_SAMPLE_RATE);
+        assert_eq!(out.len(), 16_000);
+        // Ignore the edges, where the kernel is truncated.
+        let middle = &out[1_000..15_000];
+        assert!((rms(middle) - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.01);
+
+        let crossings = middle
+            .windows(2)
+            .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+            .count();
+        // 440 Hz over 0.875 s.
+        assert!((384..=386).contains(&crossings), "{crossings}");
+    }
+
+    #[test]
+    fn resample_filters_content_above_new_nyquist() {
+        // 12 kHz cannot be represented at 16 kHz and must not alias down to 4 kHz.
+        let out = resample(&sine(12_000.0, 48_000, 0.5), 48_000, WHISPER_SAMPLE_RATE);
+        assert!(rms(&out[500..7_500]) < 0.01);
+    }
+
+    #[test]
+    fn resample_matches_the_direct_kernel_for_odd_rates() {
+        let input = sine(1_000.0, 44_100, 0.1);
+        let tabulated = resample(&input, 44_100, WHISPER_SAMPLE_RATE);
+        // More phases than the table allows, so every kernel is computed on the fly.
+        let direct = resample(&input, 44_100, 16_001);
+        assert_eq!(tabulated.len(), 1_600);
+        assert_eq!(direct.len(), 1_600);
+        for (a, b) in tabulated[100..1_500].iter().zip(&direct[100..1_500]) {
+            assert!((a - b).abs() < 0.05, "{a} vs {b}");
+        }
+    }
+
+    #[test]
+    fn resample_upsamples_and_keeps_dc() {
+        let out = resample(&[0.5; 800], 8_000, WHISPER_SAMPLE_RATE);
+        assert_eq!(out.len(), 1_600);
+        assert!(out.iter().all(|s| (s - 0.5).abs() < 1e-4));
+    }
+}
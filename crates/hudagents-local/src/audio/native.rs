@@ -0,0 +1,127 @@
+// In-process decoding with symphonia: AAC/m4a, WAV, MP3, FLAC and Ogg Vorbis.
+use super::{DecodedAudio, HAAudioError};
+use std::io::{Cursor, ErrorKind};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{CODEC_TYPE_NULL, DecoderOptions},
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+impl From<SymphoniaError> for HAAudioError {
+    fn from(e: SymphoniaError) -> Self {
+        match e {
+            SymphoniaError::Unsupported(msg) => HAAudioError::Unsupported(msg.to_string()),
+            e => HAAudioError::DecodeFailed(e.to_string()),
+        }
+    }
+}
+
+// Decodes the first audio track. Corrupt packets are skipped; a stream without a single
+// decodable packet is an error.
+pub fn decode(input: &[u8]) -> Result<DecodedAudio, HAAudioError> {
+    let source = MediaSourceStream::new(Box::new(Cursor::new(input.to_vec())), Default::default());
+    let probed = symphonia::default::get_probe().format(
+        &Hint::new(),
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut format = probed.format;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| HAAudioError::Unsupported("no audio track".to_string()))?;
+    let track_id = track.id;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut audio = DecodedAudio::default();
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(SymphoniaError::ResetRequired) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let spec = *decoded.spec();
+        let buffer = match &mut buffer {
+            Some(buffer) if buffer.capacity() >= decoded.capacity() * spec.channels.count() => {
+                buffer
+            }
+            slot => slot.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+        };
+        buffer.copy_interleaved_ref(decoded);
+        audio.samples.extend_from_slice(buffer.samples());
+        audio.sample_rate = spec.rate;
+        audio.channels = spec.channels.count();
+    }
+
+    if audio.samples.is_empty() {
+        return Err(HAAudioError::DecodeFailed("no decodable audio".to_string()));
+    }
+    Ok(audio)
+}
+
+// Decodes and converts to 16 kHz mono, ready for whisper.
+pub fn decode_for_whisper(input: &[u8]) -> Result<Vec<f32>, HAAudioError> {
+    Ok(decode(input)?.to_whisper_pcm())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav_stereo(rate: u32, frames: usize) -> Vec<u8> {
+        let data_len = (frames * 4) as u32;
+        let mut out = Vec::new();
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(36 + data_len).to_le_bytes());
+        out.extend_from_slice(b"WAVEfmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&2u16.to_le_bytes());
+        out.extend_from_slice(&rate.to_le_bytes());
+        out.extend_from_slice(&(rate * 4).to_le_bytes());
+        out.extend_from_slice(&4u16.to_le_bytes());
+        out.extend_from_slice(&16u16.to_le_bytes());
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&data_len.to_le_bytes());
+        for _ in 0..frames {
+            out.extend_from_slice(&16_384i16.to_le_bytes());
+            out.extend_from_slice(&0i16.to_le_bytes());
+        }
+        out
+    }
+
+    #[test]
+    fn decodes_wav_to_whisper_pcm() {
+        let audio = decode(&wav_stereo(48_000, 4_800)).unwrap();
+        assert_eq!(audio.sample_rate, 48_000);
+        assert_eq!(audio.channels, 2);
+        assert_eq!(audio.samples.len(), 9_600);
+
+        let pcm = audio.to_whisper_pcm();
+        assert_eq!(pcm.len(), 1_600);
+        assert!((pcm[800] - 0.25).abs() < 1e-3);
+    }
+
+    #[test]
+    fn unknown_container_is_unsupported() {
+        let err = decode(b"definitely not audio").unwrap_err();
+        assert!(matches!(err, HAAudioError::Unsupported(_)));
+    }
+}
@@ -0,0 +1,218 @@
+// Minimal RIFF/WAVE reader for uncompressed PCM, so WAV never needs a decoder.
+use super::{DecodedAudio, HAAudioError};
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+struct Format {
+    tag: u16,
+    channels: usize,
+    sample_rate: u32,
+    bits: u16,
+}
+
+// Supports 8/16/24/32-bit integer and 32-bit float samples.
+pub fn parse_wav(input: &[u8]) -> Result<DecodedAudio, HAAudioError> {
+    if input.len() < 12 || &input[..4] != b"RIFF" || &input[8..12] != b"WAVE" {
+        return Err(HAAudioError::Unsupported(
+            "not a RIFF/WAVE file".to_string(),
+        ));
+    }
+    let mut format = None;
+    let mut at = 12;
+    while input.len().saturating_sub(at) >= 8 {
+        let id = &input[at..at + 4];
+        let len = u32::from_le_bytes([input[at + 4], input[at + 5], input[at + 6], input[at + 7]]);
+        let body = at + 8;
+        // Streamed WAVs leave the data size at u32::MAX, some at 0. A 0 is only trusted when no
+        // chunk follows, otherwise it is a genuinely empty `data` chunk.
+        let end = match (id, len) {
+            (b"data", u32::MAX) => input.len(),
+            (b"data", 0) if !is_chunk_header(&input[body..]) => input.len(),
+            _ => body.saturating_add(len as usize).min(input.len()),
+        };
+        match id {
+            b"fmt " => format = Some(parse_format(&input[body..end])?),
+            b"data" => {
+                let format = format.ok_or_else(|| {
+                    HAAudioError::DecodeFailed("data chunk before fmt chunk".to_string())
+                })?;
+                return Ok(DecodedAudio {
+                    samples: decode_samples(&input[body..end], &format)?,
+                    sample_rate: format.sample_rate,
+                    channels: format.channels,
+                });
+            }
+            _ => {}
+        }
+        // Saturating: a length near u32::MAX must not wrap the offset on 32-bit targets.
+        let next = body
+            .saturating_add(len as usize)
+            .saturating_add(len as usize & 1);
+        if next <= at {
+            break;
+        }
+        at = next;
+    }
+    Err(HAAudioError::DecodeFailed("missing data chunk".to_string()))
+}
+
+// Whether `bytes` start with a plausible RIFF chunk: a printable ASCII id and a length that fits.
+pub fn is_chunk_header(bytes: &[u8]) -> bool {
+    let (Some(id), Some(len)) = (bytes.get(..4), bytes.get(4..8)) else {
+        return false;
+    };
+    let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+    id.iter().all(|b| b.is_ascii_graphic() || *b == b' ') && len <= bytes.len() - 8
+}
+
+fn parse_format(chunk: &[u8]) -> Result<Format, HAAudioError> {
+    if chunk.len() < 16 {
+        return Err(HAAudioError::DecodeFailed(
+            "fmt chunk too short".to_string(),
+        ));
+    }
+    let u16_at = |at: usize| u16::from_le_bytes([chunk[at], chunk[at + 1]]);
+    let mut tag = u16_at(0);
+    // WAVE_FORMAT_EXTENSIBLE keeps the real format in the first two bytes of the sub-format GUID.
+    if tag == FORMAT_EXTENSIBLE && chunk.len() >= 26 {
+        tag = u16_at(24);
+    }
+    let format = Format {
+        tag,
+        channels: usize::from(u16_at(2)),
+        sample_rate: u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]),
+        bits: u16_at(14),
+    };
+    if format.channels == 0 {
+        return Err(HAAudioError::DecodeFailed("zero channels".to_string()));
+    }
+    if format.sample_rate == 0 {
+        return Err(HAAudioError::DecodeFailed("zero sample rate".to_string()));
+    }
+    Ok(format)
+}
+
+fn decode_samples(data: &[u8], format: &Format) -> Result<Vec<f32>, HAAudioError> {
+    let samples = match (format.tag, format.bits) {
+        (FORMAT_PCM, 8) => data
+            .iter()
+            .map(|&b| (f32::from(b) - 128.0) / 128.0)
+            .collect(),
+        (FORMAT_PCM, 16) => data
+            .chunks_exact(2)
+            .map(|b| f32::from(i16::from_le_bytes([b[0], b[1]])) / 32_768.0)
+            .collect(),
+        (FORMAT_PCM, 24) => data
+            .chunks_exact(3)
+            .map(|b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_608.0)
+            .collect(),
+        (FORMAT_PCM, 32) => data
+            .chunks_exact(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0)
+            .collect(),
+        (FORMAT_FLOAT, 32) => data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        (tag, bits) => {
+            return Err(HAAudioError::Unsupported(format!(
+                "WAV format {tag} with {bits} bits per sample"
+            )));
+        }
+    };
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav(tag: u16, channels: u16, rate: u32, bits: u16, data: &[u8]) -> Vec<u8> {
+        let block = channels * bits / 8;
+        let mut out = Vec::new();
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        out.extend_from_slice(b"WAVEfmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&tag.to_le_bytes());
+        out.extend_from_slice(&channels.to_le_bytes());
+        out.extend_from_slice(&rate.to_le_bytes());
+        out.extend_from_slice(&(rate * u32::from(block)).to_le_bytes());
+        out.extend_from_slice(&block.to_le_bytes());
+        out.extend_from_slice(&bits.to_le_bytes());
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    #[test]
+    fn parses_16_bit_pcm() {
+        let data: Vec<u8> = [16_384i16, -32_768]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let audio = parse_wav(&wav(FORMAT_PCM, 1, 16_000, 16, &data)).unwrap();
+        assert_eq!(audio.sample_rate, 16_000);
+        assert_eq!(audio.channels, 1);
+        assert_eq!(audio.samples, vec![0.5, -1.0]);
+    }
+
+    #[test]
+    fn parses_float_and_24_bit_samples() {
+        let data: Vec<u8> = [0.25f32, -0.5]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let audio = parse_wav(&wav(FORMAT_FLOAT, 2, 48_000, 32, &data)).unwrap();
+        assert_eq!((audio.channels, audio.samples), (2, vec![0.25, -0.5]));
+
+        let audio = parse_wav(&wav(FORMAT_PCM, 1, 8_000, 24, &[0x00, 0x00, 0xc0])).unwrap();
+        assert_eq!(audio.samples, vec![-0.5]);
+    }
+
+    #[test]
+    fn rejects_compressed_and_non_wav_input() {
+        // 2 = Microsoft ADPCM.
+        assert!(matches!(
+            parse_wav(&wav(2, 1, 8_000, 4, &[0; 4])),
+            Err(HAAudioError::Unsupported(_))
+        ));
+        assert!(matches!(
+            parse_wav(b"OggS not a wav"),
+            Err(HAAudioError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn zero_data_size_reads_to_end_only_when_no_chunk_follows() {
+        let mut streamed = wav(FORMAT_PCM, 1, 16_000, 16, &[]);
+        streamed.extend_from_slice(&[0x00, 0x40, 0x00, 0xc0]);
+        assert_eq!(parse_wav(&streamed).unwrap().samples, vec![0.5, -0.5]);
+
+        let mut tagged = wav(FORMAT_PCM, 1, 16_000, 16, &[]);
+        tagged.extend_from_slice(b"LIST\x04\x00\x00\x00INFO");
+        assert!(parse_wav(&tagged).unwrap().samples.is_empty());
+    }
+
+    #[test]
+    fn zero_sample_rate_is_rejected() {
+        assert!(matches!(
+            parse_wav(&wav(FORMAT_PCM, 1, 0, 16, &[0; 4])),
+            Err(HAAudioError::DecodeFailed(msg)) if msg == "zero sample rate"
+        ));
+    }
+
+    #[test]
+    fn huge_chunk_length_ends_the_walk() {
+        let mut input = b"RIFF\0\0\0\0WAVEjunk".to_vec();
+        input.extend_from_slice(&u32::MAX.to_le_bytes());
+        input.extend_from_slice(&[0; 16]);
+        assert!(matches!(
+            parse_wav(&input),
+            Err(HAAudioError::DecodeFailed(msg)) if msg == "missing data chunk"
+        ));
+    }
+}
@@ -1,3 +1,6 @@
+pub mod audio;
+pub mod ollama;
+pub mod vad;
 pub mod whisper;
 
 pub fn add(left: u64, right: u64) -> u64 {
@@ -0,0 +1,178 @@
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt::{self, Debug, Display},
+    sync::OnceLock,
+    time::Duration,
+};
+
+pub const DEFAULT_OLLAMA_URL: &str = "http://localhost:11434";
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// Vision models on a Pi-class board can take well over a minute per image.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug)]
+pub enum HAOllamaError {
+    HttpRequestFailed(reqwest::Error),
+    HttpStatus(reqwest::StatusCode),
+}
+
+impl Display for HAOllamaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HAOllamaError::HttpRequestFailed(e) => write!(
+                f,
+                "Ollama request failed: {}. Is Ollama running (`ollama serve`)?",
+                e
+            ),
+            HAOllamaError::HttpStatus(status) => {
+                write!(f, "Ollama returned HTTP status: {}", status.as_u16())
+            }
+        }
+    }
+}
+
+impl Error for HAOllamaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HAOllamaError::HttpRequestFailed(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct GenerateRequest<'a> {
+    model: &'a str,
+    prompt: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
+    stream: bool,
+}
+
+#[derive(Deserialize)]
+struct GenerateResponse {
+    response: String,
+}
+
+// Blocking client for the Ollama `/api/generate` endpoint. The HTTP client is built on the
+// first request, so the value can be created anywhere, including inside a tokio runtime;
+// `generate` itself must run on a blocking thread (e.g. a blocking agent in the runner).
+pub struct HALocalOllama {
+    base_url: String,
+    connect_timeout: Duration,
+    timeout: Duration,
+    client: OnceLock<reqwest::blocking::Client>,
+}
+
+impl Debug for HALocalOllama {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HALocalOllama")
+            .field("base_url", &self.base_url)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl HALocalOllama {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+            client: OnceLock::new(),
+        }
+    }
+
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    // Bounds the whole request, so a stalled server fails the call instead of hanging it.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn client(&self) -> Result<&reqwest::blocking::Client, HAOllamaError> {
+        if let Some(client) = self.client.get() {
+            return Ok(client);
+        }
+        let client = reqwest::blocking::Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.timeout)
+            .build()
+            .map_err(HAOllamaError::HttpRequestFailed)?;
+        Ok(self.client.get_or_init(|| client))
+    }
+
+    // Sends `prompt` (and the images, if any) to `model` and returns the full response text.
+    pub fn generate(
+        &self,
+        model: &str,
+        prompt: &str,
+        images: &[&[u8]],
+    ) -> Result<String, HAOllamaError> {
+        let request = GenerateRequest {
+            model,
+            prompt,
+            images: images.iter().map(|image| BASE64.encode(image)).collect(),
+            stream: false,
+        };
+        let response = self
+            .client()?
+            .post(format!("{}/api/generate", self.base_url))
+            .json(&request)
+            .send()
+            .map_err(HAOllamaError::HttpRequestFailed)?;
+        if !response.status().is_success() {
+            return Err(HAOllamaError::HttpStatus(response.status()));
+        }
+        let body: GenerateResponse = response.json().map_err(HAOllamaError::HttpRequestFailed)?;
+        Ok(body.response)
+    }
+}
+
+impl Default for HALocalOllama {
+    fn default() -> Self {
+        Self::new(DEFAULT_OLLAMA_URL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_trims_trailing_slash() {
+        let ollama = HALocalOllama::new("http://localhost:11434/");
+        assert_eq!(ollama.base_url(), "http://localhost:11434");
+    }
+
+    #[test]
+    fn test_new_does_not_build_client() {
+        let ollama = HALocalOllama::default().with_timeout(Duration::from_secs(1));
+        assert_eq!(ollama.timeout(), Duration::from_secs(1));
+        assert!(ollama.client.get().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "heavy_tests")]
+    fn test_generate_fails_when_server_unreachable() {
+        let ollama =
+            HALocalOllama::new("http://127.0.0.1:9").with_connect_timeout(Duration::from_secs(1));
+        let result = ollama.generate("qwen3-vl", "describe", &[b"img"]);
+        assert!(matches!(result, Err(HAOllamaError::HttpRequestFailed(_))));
+    }
+}
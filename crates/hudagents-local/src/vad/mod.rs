@@ -0,0 +1,454 @@
+// Voice activity detection on 16 kHz mono PCM, so silence and background noise never reach
+// whisper (which tends to hallucinate text on them).
+#[cfg(feature = "silero-vad")]
+mod silero;
+
+#[cfg(feature = "silero-vad")]
+pub use silero::SileroVad;
+
+use crate::audio::{PcmAudio, WHISPER_SAMPLE_RATE};
+use std::{
+    error::Error,
+    fmt::{self, Debug, Display},
+    mem,
+    time::Duration,
+};
+
+#[derive(Debug)]
+pub enum HAVadError {
+    ModelInitFailed(String),
+    DetectionFailed(String),
+}
+
+impl Display for HAVadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HAVadError::ModelInitFailed(msg) => {
+                write!(f, "failed to initialize VAD model: {}", msg)
+            }
+            HAVadError::DetectionFailed(msg) => {
+                write!(f, "voice activity detection failed: {}", msg)
+            }
+        }
+    }
+}
+
+impl Error for HAVadError {}
+
+fn samples(duration: Duration) -> usize {
+    (duration.as_secs_f64() * f64::from(WHISPER_SAMPLE_RATE)).round() as usize
+}
+
+fn duration(samples: usize) -> Duration {
+    Duration::from_secs_f64(samples as f64 / f64::from(WHISPER_SAMPLE_RATE))
+}
+
+#[derive(Clone, Debug)]
+pub struct VadConfig {
+    // Analysis frame length.
+    pub frame: Duration,
+    // Frames quieter than this (RMS, in dBFS) are silence.
+    pub energy_threshold_db: f32,
+    // Frames whose zero-crossing rate (crossings per sample) is above this are noise, e.g. hiss
+    // or wind, even when loud enough.
+    pub max_zero_crossing_rate: f32,
+    // Utterances with less voiced audio than this are dropped as clicks and bumps.
+    pub min_speech: Duration,
+    // How long the voice has to stay silent before an utterance ends. Shorter pauses are kept
+    // inside the utterance.
+    pub hangover: Duration,
+    // Audio kept before and after the voiced part, so soft word onsets and endings survive.
+    pub padding: Duration,
+}
+
+impl VadConfig {
+    pub fn new() -> Self {
+        Self {
+            frame: Duration::from_millis(20),
+            energy_threshold_db: -45.0,
+            max_zero_crossing_rate: 0.35,
+            min_speech: Duration::from_millis(200),
+            hangover: Duration::from_millis(400),
+            padding: Duration::from_millis(100),
+        }
+    }
+
+    pub fn with_frame(mut self, frame: Duration) -> Self {
+        self.frame = frame;
+        self
+    }
+
+    pub fn with_energy_threshold_db(mut self, db: f32) -> Self {
+        self.energy_threshold_db = db;
+        self
+    }
+
+    pub fn with_max_zero_crossing_rate(mut self, rate: f32) -> Self {
+        self.max_zero_crossing_rate = rate;
+        self
+    }
+
+    pub fn with_min_speech(mut self, min_speech: Duration) -> Self {
+        self.min_speech = min_speech;
+        self
+    }
+
+    pub fn with_hangover(mut self, hangover: Duration) -> Self {
+        self.hangover = hangover;
+        self
+    }
+
+    pub fn with_padding(mut self, padding: Duration) -> Self {
+        self.padding = padding;
+        self
+    }
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// A stretch of speech, as sample offsets (16 kHz) into the analysed PCM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpeechSpan {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl SpeechSpan {
+    pub fn len(&self) -> usize {
+        self.end.saturating_sub(self.start)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end <= self.start
+    }
+
+    pub fn start_time(&self) -> Duration {
+        duration(self.start)
+    }
+
+    pub fn end_time(&self) -> Duration {
+        duration(self.end)
+    }
+}
+
+pub trait VoiceDetector: Send + Sync {
+    // Speech spans of 16 kHz mono `pcm`, in order and not overlapping.
+    fn speech_spans(&self, pcm: &[f32]) -> Result<Vec<SpeechSpan>, HAVadError>;
+}
+
+// Joins the speech spans of `pcm`, leaving out everything in between. Spans are clipped to
+// `pcm`; empty or reversed ones add nothing.
+pub fn speech_only(pcm: &[f32], spans: &[SpeechSpan]) -> Vec<f32> {
+    let clipped = spans.iter().map(|span| {
+        let end = span.end.min(pcm.len());
+        span.start.min(end)..end
+    });
+    let mut out = Vec::with_capacity(clipped.clone().map(|range| range.len()).sum());
+    for range in clipped {
+        out.extend_from_slice(&pcm[range]);
+    }
+    out
+}
+
+// RMS level in dBFS and zero crossings per sample.
+pub fn frame_features(frame: &[f32]) -> (f32, f32) {
+    if frame.is_empty() {
+        return (f32::NEG_INFINITY, 0.0);
+    }
+    let power = frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32;
+    let crossings = frame
+        .windows(2)
+        .filter(|pair| (pair[0] >= 0.0) != (pair[1] >= 0.0))
+        .count();
+    (10.0 * power.log10(), crossings as f32 / frame.len() as f32)
+}
+
+// Frame-wise energy and zero-crossing-rate detector. Cheap and dependency free; good enough to
+// tell speech from silence and steady noise, less so speech from music or chatter.
+#[derive(Clone, Debug, Default)]
+pub struct EnergyVad {
+    config: VadConfig,
+}
+
+impl EnergyVad {
+    pub fn new(config: VadConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &VadConfig {
+        &self.config
+    }
+
+    pub fn is_speech(&self, frame: &[f32]) -> bool {
+        let (db, zcr) = frame_features(frame);
+        db >= self.config.energy_threshold_db && zcr <= self.config.max_zero_crossing_rate
+    }
+}
+
+impl VoiceDetector for EnergyVad {
+    fn speech_spans(&self, pcm: &[f32]) -> Result<Vec<SpeechSpan>, HAVadError> {
+        let mut segmenter = UtteranceSegmenter::new(self.clone());
+        let mut spans: Vec<SpeechSpan> = segmenter.push(pcm).iter().map(Utterance::span).collect();
+        spans.extend(segmenter.finish().as_ref().map(Utterance::span));
+        Ok(spans)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Utterance {
+    // Sample offset (16 kHz) of the first sample since the segmenter started.
+    pub start: usize,
+    pub samples: Vec<f32>,
+}
+
+impl Utterance {
+    pub fn span(&self) -> SpeechSpan {
+        SpeechSpan {
+            start: self.start,
+            end: self.start + self.samples.len(),
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        duration(self.samples.len())
+    }
+}
+
+impl From<Utterance> for PcmAudio {
+    fn from(utterance: Utterance) -> Self {
+        PcmAudio::f32(utterance.samples, WHISPER_SAMPLE_RATE, 1)
+    }
+}
+
+// Splits a PCM stream into utterances as it arrives. Silence between utterances is dropped.
+pub struct UtteranceSegmenter {
+    vad: EnergyVad,
+    frame: usize,
+    min_speech: usize,
+    hangover: usize,
+    padding: usize,
+    // Samples of the frame being filled.
+    pending: Vec<f32>,
+    // Offset of the next full frame.
+    position: usize,
+    // The utterance in progress; while silent, the last `padding` samples of silence.
+    current: Vec<f32>,
+    current_start: usize,
+    in_speech: bool,
+    voiced: usize,
+    // Silence at the end of `current`.
+    trailing_silence: usize,
+}
+
+impl UtteranceSegmenter {
+    pub fn new(vad: EnergyVad) -> Self {
+        let config = vad.config();
+        Self {
+            frame: samples(config.frame).max(1),
+            min_speech: samples(config.min_speech),
+            hangover: samples(config.hangover),
+            padding: samples(config.padding),
+            vad,
+            pending: Vec::new(),
+            position: 0,
+            current: Vec::new(),
+            current_start: 0,
+            in_speech: false,
+            voiced: 0,
+            trailing_silence: 0,
+        }
+    }
+
+    // Takes 16 kHz mono samples and returns the utterances they completed.
+    pub fn push(&mut self, mut samples: &[f32]) -> Vec<Utterance> {
+        let mut done = Vec::new();
+        while !samples.is_empty() {
+            let take = (self.frame - self.pending.len()).min(samples.len());
+            self.pending.extend_from_slice(&samples[..take]);
+            samples = &samples[take..];
+            if self.pending.len() == self.frame {
+                let frame = mem::take(&mut self.pending);
+                done.extend(self.push_frame(&frame));
+            }
+        }
+        done
+    }
+
+    // Ends the stream, returning the utterance still in progress if it is long enough.
+    pub fn finish(&mut self) -> Option<Utterance> {
+        if self.in_speech {
+            let pending = mem::take(&mut self.pending);
+            self.current.extend_from_slice(&pending);
+            self.trailing_silence += pending.len();
+        }
+        let utterance = self.end_utterance();
+        self.pending.clear();
+        self.current.clear();
+        self.position = 0;
+        self.current_start = 0;
+        utterance
+    }
+
+    fn push_frame(&mut self, frame: &[f32]) -> Option<Utterance> {
+        let speech = self.vad.is_speech(frame);
+        self.position += frame.len();
+        if !self.in_speech {
+            self.current.extend_from_slice(frame);
+            if speech {
+                self.in_speech = true;
+                self.voiced = frame.len();
+                self.trailing_silence = 0;
+            } else {
+                // Keep only the padding before a possible onset.
+                let excess = self.current.len().saturating_sub(self.padding);
+                self.current.drain(..excess);
+            }
+            self.current_start = self.position - self.current.len();
+            return None;
+        }
+
+        self.current.extend_from_slice(frame);
+        if speech {
+            self.voiced += frame.len();
+            self.trailing_silence = 0;
+            return None;
+        }
+        self.trailing_silence += frame.len();
+        if self.trailing_silence < self.hangover.max(1) {
+            return None;
+        }
+        // The silence after the utterance doubles as padding for the next one.
+        let silence_start = self.current.len() - self.trailing_silence.min(self.padding);
+        let silence = self.current[silence_start..].to_vec();
+        let utterance = self.end_utterance();
+        self.current = silence;
+        self.current_start = self.position - self.current.len();
+        utterance
+    }
+
+    fn end_utterance(&mut self) -> Option<Utterance> {
+        let was_speech = mem::replace(&mut self.in_speech, false);
+        let voiced = mem::take(&mut self.voiced);
+        if !was_speech || voiced < self.min_speech {
+            self.current.clear();
+            return None;
+        }
+        // Trim silence past the padding, e.g. when the stream ended mid-hangover.
+        let keep = self.current.len() - self.trailing_silence.saturating_sub(self.padding);
+        self.trailing_silence = 0;
+        let mut samples = mem::take(&mut self.current);
+        samples.truncate(keep);
+        Some(Utterance {
+            start: self.current_start,
+            samples,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: usize = 16;
+
+    fn tone(ms: usize) -> Vec<f32> {
+        // 200 Hz at -13 dBFS.
+        (0..ms * MS)
+            .map(|i| 0.3 * (i as f32 * 2.0 * std::f32::consts::PI * 200.0 / 16_000.0).sin())
+            .collect()
+    }
+
+    fn silence(ms: usize) -> Vec<f32> {
+        vec![0.0; ms * MS]
+    }
+
+    fn concat(parts: &[Vec<f32>]) -> Vec<f32> {
+        parts.concat()
+    }
+
+    #[test]
+    fn frame_features_separate_tone_from_hiss() {
+        let vad = EnergyVad::default();
+        assert!(vad.is_speech(&tone(20)));
+        assert!(!vad.is_speech(&silence(20)));
+        // Alternating samples cross zero every sample, like white noise at its worst.
+        let hiss: Vec<f32> = (0..320)
+            .map(|i| if i % 2 == 0 { 0.3 } else { -0.3 })
+            .collect();
+        assert!(!vad.is_speech(&hiss));
+    }
+
+    #[test]
+    fn speech_spans_pad_utterances_and_drop_silence() {
+        let pcm = concat(&[silence(500), tone(600), silence(1000)]);
+        let spans = EnergyVad::default().speech_spans(&pcm).unwrap();
+
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].start_time(), Duration::from_millis(400));
+        assert_eq!(spans[0].end_time(), Duration::from_millis(1200));
+        assert!(
+            EnergyVad::default()
+                .speech_spans(&silence(2000))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn hangover_bridges_short_pauses_and_min_speech_drops_blips() {
+        let vad = EnergyVad::new(VadConfig::new().with_padding(Duration::ZERO));
+        let pcm = concat(&[tone(300), silence(200), tone(300), silence(1000), tone(100)]);
+        let spans = vad.speech_spans(&pcm).unwrap();
+
+        assert_eq!(
+            spans,
+            [SpeechSpan {
+                start: 0,
+                end: 800 * MS
+            }]
+        );
+        assert_eq!(speech_only(&pcm, &spans).len(), 800 * MS);
+    }
+
+    #[test]
+    fn speech_only_clips_bad_spans() {
+        let pcm = [0.1, 0.2, 0.3];
+        let reversed = SpeechSpan { start: 2, end: 1 };
+        assert_eq!(reversed.len(), 0);
+        assert!(reversed.is_empty());
+
+        let spans = [
+            reversed,
+            SpeechSpan { start: 1, end: 9 },
+            SpeechSpan { start: 5, end: 7 },
+        ];
+        assert_eq!(speech_only(&pcm, &spans), vec![0.2, 0.3]);
+    }
+
+    #[test]
+    fn segmenter_streams_in_small_chunks() {
+        let pcm = concat(&[
+            silence(300),
+            tone(400),
+            silence(600),
+            tone(500),
+            silence(100),
+        ]);
+        let mut segmenter = UtteranceSegmenter::new(EnergyVad::default());
+        let mut utterances: Vec<Utterance> = pcm
+            .chunks(7 * MS + 3)
+            .flat_map(|c| segmenter.push(c))
+            .collect();
+        utterances.extend(segmenter.finish());
+
+        let spans: Vec<SpeechSpan> = utterances.iter().map(Utterance::span).collect();
+        assert_eq!(spans, EnergyVad::default().speech_spans(&pcm).unwrap());
+        assert_eq!(spans.len(), 2);
+        assert_eq!(utterances[1].start, 1200 * MS);
+        assert_eq!(utterances[1].duration(), Duration::from_millis(700));
+    }
+}
@@ -0,0 +1,142 @@
+// Silero VAD through whisper.cpp. Needs a ggml Silero model, e.g. `ggml-silero-v5.1.2.bin`.
+use super::{HAVadError, SpeechSpan, VadConfig, VoiceDetector};
+use crate::audio::WHISPER_SAMPLE_RATE;
+use std::{
+    path::Path,
+    sync::mpsc::{self, Sender},
+    thread,
+};
+use whisper_rs::{WhisperVadContext, WhisperVadContextParams, WhisperVadParams};
+
+// whisper.cpp reports VAD segments in centiseconds.
+const SAMPLES_PER_CENTISECOND: f32 = WHISPER_SAMPLE_RATE as f32 / 100.0;
+
+// The detection settings, plain data so they can cross to the worker.
+#[derive(Clone, Copy, Debug)]
+struct Settings {
+    threshold: f32,
+    min_speech_ms: i32,
+    min_silence_ms: i32,
+    speech_pad_ms: i32,
+}
+
+impl Settings {
+    fn params(self) -> WhisperVadParams {
+        let mut params = WhisperVadParams::new();
+        params.set_threshold(self.threshold);
+        params.set_min_speech_duration(self.min_speech_ms);
+        params.set_min_silence_duration(self.min_silence_ms);
+        params.set_speech_pad(self.speech_pad_ms);
+        params
+    }
+}
+
+// Segment start and end, in centiseconds.
+type Segments = Result<Vec<(f32, f32)>, HAVadError>;
+
+struct Job {
+    pcm: Vec<f32>,
+    settings: Settings,
+    reply: Sender<Segments>,
+}
+
+pub struct SileroVad {
+    jobs: Sender<Job>,
+    settings: Settings,
+}
+
+impl SileroVad {
+    // Uses `min_speech`, `hangover` and `padding` of the config; the energy and zero-crossing
+    // thresholds do not apply. The model is loaded once, on a worker thread that owns it for
+    // the detector's lifetime: whisper-rs does not let the context move between threads.
+    pub fn new(model_path: impl Into<String>, config: VadConfig) -> Result<Self, HAVadError> {
+        let model_path = model_path.into();
+        if !Path::new(&model_path).is_file() {
+            return Err(HAVadError::ModelInitFailed(format!(
+                "VAD model not found at: {}",
+                model_path
+            )));
+        }
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let (ready, loaded) = mpsc::channel();
+        thread::Builder::new()
+            .name("silero-vad".to_string())
+            .spawn(move || {
+                let ctx = WhisperVadContext::new(&model_path, WhisperVadContextParams::new());
+                let mut ctx = match ctx {
+                    Ok(ctx) => {
+                        let _ = ready.send(Ok(()));
+                        ctx
+                    }
+                    Err(e) => {
+                        let _ = ready.send(Err(HAVadError::ModelInitFailed(format!("{:?}", e))));
+                        return;
+                    }
+                };
+                // Ends once the detector, and with it the last sender, is dropped.
+                for job in queue {
+                    let segments = ctx
+                        .segments_from_samples(job.settings.params(), &job.pcm)
+                        .map(|segments| segments.map(|s| (s.start, s.end)).collect())
+                        .map_err(|e| HAVadError::DetectionFailed(format!("{:?}", e)));
+                    let _ = job.reply.send(segments);
+                }
+            })
+            .map_err(|e| HAVadError::ModelInitFailed(e.to_string()))?;
+        loaded.recv().map_err(|_| {
+            HAVadError::ModelInitFailed("VAD worker exited while loading".to_string())
+        })??;
+        Ok(Self {
+            jobs,
+            settings: Settings {
+                threshold: 0.5,
+                min_speech_ms: config.min_speech.as_millis() as i32,
+                min_silence_ms: config.hangover.as_millis() as i32,
+                speech_pad_ms: config.padding.as_millis() as i32,
+            },
+        })
+    }
+
+    // Speech probability above which a frame counts as speech.
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.settings.threshold = threshold;
+        self
+    }
+}
+
+impl VoiceDetector for SileroVad {
+    // Calls on the same detector queue up on its worker and run one at a time.
+    fn speech_spans(&self, pcm: &[f32]) -> Result<Vec<SpeechSpan>, HAVadError> {
+        let worker_gone = || HAVadError::DetectionFailed("VAD worker has stopped".to_string());
+        let (reply, answer) = mpsc::channel();
+        self.jobs
+            .send(Job {
+                pcm: pcm.to_vec(),
+                settings: self.settings,
+                reply,
+            })
+            .map_err(|_| worker_gone())?;
+        let segments = answer.recv().map_err(|_| worker_gone())??;
+        Ok(segments
+            .into_iter()
+            .map(|(start, end)| SpeechSpan {
+                start: ((start * SAMPLES_PER_CENTISECOND) as usize).min(pcm.len()),
+                end: ((end * SAMPLES_PER_CENTISECOND) as usize).min(pcm.len()),
+            })
+            .filter(|span| !span.is_empty())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_fails_without_model() {
+        let err = SileroVad::new("/nonexistent/ggml-silero.bin", VadConfig::new())
+            .err()
+            .unwrap();
+        assert!(matches!(err, HAVadError::ModelInitFailed(_)));
+    }
+}
@@ -39,7 +39,7 @@ impl Display for HAWhisperError {
                 write!(f, "Missing dependency: {}. Please install it.", dep)
             }
             HAWhisperError::DecodeFailed(msg) => {
-                write!(f, "ffmpeg failed to decode input: {}", msg)
+                write!(f, "failed to decode audio input: {}", msg)
             }
             HAWhisperError::HttpRequestFailed(msg) => write!(f, "HTTP request failed: {}", msg),
             HAWhisperError::HttpStatus(status) => write!(f, "HTTP status: {}", status.as_u16()),